@@ -0,0 +1,185 @@
+use std::f32::consts::FRAC_PI_2;
+
+use bevy::{
+    input::mouse::{MouseScrollUnit, MouseWheel},
+    math::Vec3Swizzles,
+    prelude::*,
+};
+
+use crate::{
+    config::Keymap,
+    controls::{CursorOverGui, SelectedTower},
+    AppState, MAP_HEIGHT, MAP_WIDTH,
+};
+
+/// Pixels from the window border where the cursor starts panning the camera
+const EDGE_SCROLL_MARGIN: f32 = 10.;
+/// World units per second at the default zoom
+const PAN_SPEED: f32 = 10.;
+const MIN_DISTANCE: f32 = 5.;
+const MAX_DISTANCE: f32 = 40.;
+/// Angle between the ground and the camera of a perfect isometric view
+const PITCH: f32 = -35.264;
+
+pub struct CameraControlsPlugin;
+
+impl Plugin for CameraControlsPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(
+            (
+                CameraController::pan,
+                CameraController::zoom,
+                CameraController::rotate,
+                CameraController::focus_selection,
+                CameraController::apply,
+            )
+                .chain()
+                .distributive_run_if(in_state(AppState::InGame)),
+        );
+    }
+}
+
+/// Orbits the camera around a point on the ground
+#[derive(Component)]
+pub struct CameraController {
+    pub focus: Vec2,
+    pub distance: f32,
+    /// Quarter turns away from the starting view
+    pub quarter_turns: u8,
+}
+
+impl Default for CameraController {
+    fn default() -> Self {
+        Self {
+            focus: Vec2::new(MAP_WIDTH as f32 / 2., MAP_HEIGHT as f32 / 2.),
+            distance: MAP_WIDTH as f32 * 1.2,
+            quarter_turns: 0,
+        }
+    }
+}
+
+impl CameraController {
+    pub fn transform(&self) -> Transform {
+        let yaw = 45_f32.to_radians() + f32::from(self.quarter_turns) * FRAC_PI_2;
+        let rotation = Quat::from_euler(EulerRot::YXZ, yaw, PITCH.to_radians(), 0.);
+        let focus = self.focus.extend(0.).xzy();
+        Transform::from_translation(focus + rotation * Vec3::Z * self.distance)
+            .with_rotation(rotation)
+    }
+
+    /// Directions on the ground that move the view up and right on screen
+    fn ground_axes(&self) -> (Vec2, Vec2) {
+        let forward = (self.transform().rotation * -Vec3::Z).xz().normalize();
+        (forward, Vec2::new(-forward.y, forward.x))
+    }
+
+    fn pan(
+        time: Res<Time>,
+        keys: Res<Input<KeyCode>>,
+        keymap: Res<Keymap>,
+        windows: Query<&Window>,
+        mut cameras: Query<&mut CameraController>,
+    ) {
+        let mut direction = Vec2::ZERO;
+        for (key, step) in [
+            (keymap.pan_up, Vec2::Y),
+            (keymap.pan_down, -Vec2::Y),
+            (keymap.pan_left, -Vec2::X),
+            (keymap.pan_right, Vec2::X),
+        ] {
+            if keys.pressed(key) {
+                direction += step;
+            }
+        }
+        if let Some((window, cursor)) = windows
+            .get_single()
+            .ok()
+            .and_then(|window| Some((window, window.cursor_position()?)))
+        {
+            // Cursor positions start in the bottom left corner
+            if cursor.x < EDGE_SCROLL_MARGIN {
+                direction.x -= 1.;
+            } else if cursor.x > window.width() - EDGE_SCROLL_MARGIN {
+                direction.x += 1.;
+            }
+            if cursor.y < EDGE_SCROLL_MARGIN {
+                direction.y -= 1.;
+            } else if cursor.y > window.height() - EDGE_SCROLL_MARGIN {
+                direction.y += 1.;
+            }
+        }
+        if direction == Vec2::ZERO {
+            return;
+        }
+
+        for mut camera in &mut cameras {
+            let (up, right) = camera.ground_axes();
+            let speed = PAN_SPEED * camera.distance / CameraController::default().distance;
+            let movement = (up * direction.y + right * direction.x).normalize_or_zero()
+                * speed
+                * time.raw_delta_seconds();
+            camera.focus = (camera.focus + movement)
+                .clamp(Vec2::ZERO, Vec2::new(MAP_WIDTH as f32, MAP_HEIGHT as f32));
+        }
+    }
+
+    fn zoom(
+        mut wheel: EventReader<MouseWheel>,
+        cursor_over_gui: Res<CursorOverGui>,
+        mut cameras: Query<&mut CameraController>,
+    ) {
+        let scroll = wheel
+            .iter()
+            .map(|event| match event.unit {
+                MouseScrollUnit::Line => event.y,
+                MouseScrollUnit::Pixel => event.y / 50.,
+            })
+            .sum::<f32>();
+        if scroll == 0. || **cursor_over_gui {
+            return;
+        }
+        for mut camera in &mut cameras {
+            camera.distance =
+                (camera.distance * 0.9_f32.powf(scroll)).clamp(MIN_DISTANCE, MAX_DISTANCE);
+        }
+    }
+
+    fn rotate(
+        keys: Res<Input<KeyCode>>,
+        keymap: Res<Keymap>,
+        mut cameras: Query<&mut CameraController>,
+    ) {
+        for mut camera in &mut cameras {
+            if keys.just_pressed(keymap.rotate_left) {
+                camera.quarter_turns = (camera.quarter_turns + 3) % 4;
+            }
+            if keys.just_pressed(keymap.rotate_right) {
+                camera.quarter_turns = (camera.quarter_turns + 1) % 4;
+            }
+        }
+    }
+
+    fn focus_selection(
+        keys: Res<Input<KeyCode>>,
+        keymap: Res<Keymap>,
+        selected: Option<Res<SelectedTower>>,
+        towers: Query<&GlobalTransform>,
+        mut cameras: Query<&mut CameraController>,
+    ) {
+        if !keys.just_pressed(keymap.focus_selection) {
+            return;
+        }
+        let Some(tower) = selected.and_then(|selected| towers.get(selected.tower).ok()) else {
+            return;
+        };
+        for mut camera in &mut cameras {
+            camera.focus = tower.translation().xz();
+        }
+    }
+
+    fn apply(mut cameras: Query<(&mut Transform, &CameraController), Changed<CameraController>>) {
+        for (mut transform, camera) in &mut cameras {
+            *transform = camera.transform();
+        }
+    }
+}
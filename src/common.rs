@@ -1,37 +1,52 @@
+use std::ops::RangeBounds;
+
 use bevy::{math::Vec3Swizzles, prelude::*};
+use bevy_prototype_debug_lines::DebugLines;
 use seldom_interop::prelude::Position2;
 
 #[derive(Component)]
 pub struct TrackWorldObjectToScreenPosition {
     pub target: Entity,
     pub offset: Vec2,
+    /// Where the target was last seen, the node stays there once the target is gone
+    pub last_position: Option<Vec3>,
 }
 
 impl TrackWorldObjectToScreenPosition {
     pub fn track(
         windows: Query<&Window>,
+        ui_scale: Res<UiScale>,
         cameras: Query<(&GlobalTransform, &Camera)>,
         world_objects: Query<&GlobalTransform>,
-        mut tracking_objects: Query<(&mut Style, &TrackWorldObjectToScreenPosition)>,
+        mut tracking_objects: Query<(&mut Style, &mut TrackWorldObjectToScreenPosition)>,
     ) {
-        for (mut style, TrackWorldObjectToScreenPosition { target, offset }) in
-            &mut tracking_objects
-        {
-            if let Ok(world_pos) = world_objects.get(*target) {
+        for (mut style, mut tracking) in &mut tracking_objects {
+            if let Ok(world_pos) = world_objects.get(tracking.target) {
+                tracking.last_position = Some(world_pos.translation());
+            }
+            let TrackWorldObjectToScreenPosition {
+                offset,
+                last_position,
+                ..
+            } = *tracking;
+            if let Some(world_pos) = last_position {
                 if let Size {
                     width: Val::Px(width),
                     height: Val::Px(height),
                 } = style.size
                 {
                     let (cam_pos, camera) = cameras.single();
-                    if let Some(screen_position) =
-                        camera.world_to_viewport(cam_pos, world_pos.translation())
-                    {
+                    if let Some(screen_position) = camera.world_to_viewport(cam_pos, world_pos) {
                         let window = windows.single();
+                        // Pixel values in styles get multiplied by the UI scale
+                        let scale = ui_scale.scale as f32;
                         let new_pos = UiRect::new(
-                            Val::Px(screen_position.x - width / 2.0 + offset.x),
+                            Val::Px(screen_position.x / scale - width / 2.0 + offset.x),
                             Val::Auto,
-                            Val::Px(window.height() - screen_position.y - height / 2.0 + offset.y),
+                            Val::Px(
+                                (window.height() - screen_position.y) / scale - height / 2.0
+                                    + offset.y,
+                            ),
                             Val::Auto,
                         );
 
@@ -61,14 +76,24 @@ impl MovingTo {
 #[derive(Component, Deref, DerefMut)]
 pub struct Fadeout(pub Timer);
 
+/// Makes a [`Fadeout`] follow the raw clock instead of the game speed, for UI that has to keep
+/// fading while the game is paused or sped up
+#[derive(Component)]
+pub struct RawTime;
+
 impl Fadeout {
     pub fn fadeout(
         mut commands: Commands,
         time: Res<Time>,
-        mut fadeouts: Query<(Entity, &mut Fadeout)>,
+        mut fadeouts: Query<(Entity, &mut Fadeout, Option<&RawTime>)>,
     ) {
-        for (entity, mut timer) in &mut fadeouts {
-            if timer.tick(time.delta()).finished() {
+        for (entity, mut timer, raw) in &mut fadeouts {
+            let delta = if raw.is_some() {
+                time.raw_delta()
+            } else {
+                time.delta()
+            };
+            if timer.tick(delta).finished() {
                 commands.entity(entity).despawn_recursive();
             }
         }
@@ -90,6 +115,52 @@ impl Builds {
     }
 }
 
+/// Source of all gameplay randomness, seeded so a run can be reproduced exactly
+#[derive(Resource)]
+pub struct GameRng {
+    seed: u64,
+    state: u64,
+}
+
+impl GameRng {
+    pub fn new(seed: u64) -> Self {
+        Self { seed, state: seed }
+    }
+
+    /// Restores a generator that has already been drawn from
+    pub fn from_state(seed: u64, state: u64) -> Self {
+        Self { seed, state }
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    pub fn state(&self) -> u64 {
+        self.state
+    }
+
+    pub fn f32(&mut self) -> f32 {
+        self.draw(fastrand::Rng::f32)
+    }
+
+    pub fn u8(&mut self, range: impl RangeBounds<u8>) -> u8 {
+        self.draw(|rng| rng.u8(range))
+    }
+
+    pub fn u32(&mut self, range: impl RangeBounds<u32>) -> u32 {
+        self.draw(|rng| rng.u32(range))
+    }
+
+    // fastrand::Rng isn't Sync so it can't live in a resource, only its state is kept between draws
+    fn draw<T>(&mut self, f: impl FnOnce(&fastrand::Rng) -> T) -> T {
+        let rng = fastrand::Rng::with_seed(self.state);
+        let value = f(&rng);
+        self.state = rng.get_seed();
+        value
+    }
+}
+
 pub fn ray_from_screenspace(
     cursor_pos_screen: Vec2,
     window: &Window,
@@ -160,6 +231,20 @@ pub fn get_squares_from_pos(position: Vec2) -> [Vec2; 4] {
     ]
 }
 
+/// Draws a circle on the ground for a single frame
+pub fn draw_circle(lines: &mut DebugLines, center: Vec2, radius: f32, color: Color) {
+    const SEGMENTS: usize = 48;
+    let point = |index: usize| {
+        let angle = index as f32 / SEGMENTS as f32 * std::f32::consts::TAU;
+        (center + Vec2::from_angle(angle) * radius)
+            .extend(0.05)
+            .xzy()
+    };
+    for index in 0..SEGMENTS {
+        lines.line_colored(point(index), point(index + 1), 0., color);
+    }
+}
+
 pub fn position_within_rect(position: Vec2, rect_size: Vec2, rect_position: Vec2) -> bool {
     let left = rect_position.x - rect_size.x / 2.;
     let right = rect_position.x + rect_size.x / 2.;
@@ -0,0 +1,218 @@
+use std::{fs, path::PathBuf};
+
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::towers::GemChances;
+
+pub const CONFIG_FILE: &str = "config.ron";
+
+/// Player configuration read from [`CONFIG_FILE`], missing fields fall back to their defaults
+#[derive(Resource, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub seed: Option<u64>,
+    /// Show floating numbers for damage dealt to creeps
+    pub damage_numbers: bool,
+    /// Draw the developer debug overlay
+    pub debug_overlay: bool,
+    /// Master volume for sounds, from 0 to 1, saved ahead of the game having any audio
+    pub volume: f32,
+    /// Gem type and quality odds, and whether bad luck protection is on
+    pub gem_chances: GemChances,
+    pub keymap: Keymap,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            seed: None,
+            damage_numbers: true,
+            debug_overlay: false,
+            volume: 1.,
+            gem_chances: GemChances::default(),
+            keymap: Keymap::default(),
+        }
+    }
+}
+
+/// Keys for every action that can also be done with the mouse
+#[derive(Clone, Resource, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Keymap {
+    pub pick: KeyCode,
+    pub refine_and_pick: KeyCode,
+    /// Merge the selected gem with identical gems built this round
+    pub merge: KeyCode,
+    pub remove: KeyCode,
+    pub combine: KeyCode,
+    /// Step through the recipes and towers the selected tower can be combined with
+    pub next_combination: KeyCode,
+    pub upgrade_chance: KeyCode,
+    /// Select the next gem built this round
+    pub next_gem: KeyCode,
+    pub previous_gem: KeyCode,
+    pub save: KeyCode,
+    pub load: KeyCode,
+    pub damage_numbers: KeyCode,
+    pub pan_up: KeyCode,
+    pub pan_down: KeyCode,
+    pub pan_left: KeyCode,
+    pub pan_right: KeyCode,
+    pub rotate_left: KeyCode,
+    pub rotate_right: KeyCode,
+    /// Center the camera on the selected tower
+    pub focus_selection: KeyCode,
+    pub pause: KeyCode,
+    /// Step through the game speeds
+    pub speed: KeyCode,
+    /// Open the pause menu or go back out of a menu
+    pub menu: KeyCode,
+    /// Show or hide the recipe book
+    pub recipes: KeyCode,
+    /// Only does anything in builds with the `dev-tools` feature
+    pub debug_overlay: KeyCode,
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        Self {
+            pick: KeyCode::Space,
+            refine_and_pick: KeyCode::R,
+            merge: KeyCode::M,
+            remove: KeyCode::Delete,
+            combine: KeyCode::C,
+            next_combination: KeyCode::X,
+            upgrade_chance: KeyCode::U,
+            next_gem: KeyCode::Tab,
+            previous_gem: KeyCode::Back,
+            save: KeyCode::F5,
+            load: KeyCode::F9,
+            damage_numbers: KeyCode::N,
+            pan_up: KeyCode::W,
+            pan_down: KeyCode::S,
+            pan_left: KeyCode::A,
+            pan_right: KeyCode::D,
+            rotate_left: KeyCode::Q,
+            rotate_right: KeyCode::E,
+            focus_selection: KeyCode::F,
+            pause: KeyCode::P,
+            speed: KeyCode::Period,
+            menu: KeyCode::Escape,
+            recipes: KeyCode::B,
+            debug_overlay: KeyCode::F3,
+        }
+    }
+}
+
+impl Keymap {
+    /// Every action with a readable name, in the order the settings screen lists them
+    pub fn bindings_mut(&mut self) -> Vec<(&'static str, &mut KeyCode)> {
+        #[cfg_attr(not(feature = "dev-tools"), allow(unused_mut))]
+        let mut bindings = vec![
+            ("Pick", &mut self.pick),
+            ("Refine and pick", &mut self.refine_and_pick),
+            ("Merge", &mut self.merge),
+            ("Remove", &mut self.remove),
+            ("Combine", &mut self.combine),
+            ("Next combination", &mut self.next_combination),
+            ("Upgrade chance", &mut self.upgrade_chance),
+            ("Next gem", &mut self.next_gem),
+            ("Previous gem", &mut self.previous_gem),
+            ("Save", &mut self.save),
+            ("Load", &mut self.load),
+            ("Damage numbers", &mut self.damage_numbers),
+            ("Pan up", &mut self.pan_up),
+            ("Pan down", &mut self.pan_down),
+            ("Pan left", &mut self.pan_left),
+            ("Pan right", &mut self.pan_right),
+            ("Rotate left", &mut self.rotate_left),
+            ("Rotate right", &mut self.rotate_right),
+            ("Focus selection", &mut self.focus_selection),
+            ("Pause", &mut self.pause),
+            ("Game speed", &mut self.speed),
+            ("Menu", &mut self.menu),
+            ("Recipe book", &mut self.recipes),
+        ];
+        #[cfg(feature = "dev-tools")]
+        bindings.push(("Debug overlay", &mut self.debug_overlay));
+        bindings
+    }
+
+    /// Binds an action to a key, an action that already had the key gets the old key of the one
+    /// being rebound so no two actions ever share a key
+    pub fn rebind(&mut self, index: usize, key: KeyCode) {
+        let mut bindings = self.bindings_mut();
+        let Some(old) = bindings.get(index).map(|(_, binding)| **binding) else {
+            return;
+        };
+        for (_, binding) in &mut bindings {
+            if **binding == key {
+                **binding = old;
+            }
+        }
+        *bindings[index].1 = key;
+    }
+
+    pub fn binding(&self, index: usize) -> Option<(&'static str, KeyCode)> {
+        let mut keymap = self.clone();
+        let (name, key) = keymap.bindings_mut().into_iter().nth(index)?;
+        Some((name, *key))
+    }
+}
+
+impl Config {
+    pub fn load() -> Self {
+        match fs::read_to_string(CONFIG_FILE) {
+            Ok(text) => ron::from_str(&text).unwrap_or_else(|err| {
+                eprintln!("Could not read {CONFIG_FILE}, using defaults: {err}");
+                Self::default()
+            }),
+            Err(_) => Self::default(),
+        }
+    }
+
+    pub fn save(&self) -> Result<(), String> {
+        let text = ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())
+            .map_err(|err| format!("Could not serialize config: {err}"))?;
+        fs::write(CONFIG_FILE, text).map_err(|err| format!("Could not write {CONFIG_FILE}: {err}"))
+    }
+}
+
+/// Command line arguments, these override anything set in [`Config`]
+#[derive(Default)]
+pub struct Args {
+    pub seed: Option<u64>,
+    pub record: Option<PathBuf>,
+    pub replay: Option<PathBuf>,
+    pub headless: Option<PathBuf>,
+}
+
+impl Args {
+    pub fn parse() -> Self {
+        let mut parsed = Self::default();
+        let mut args = std::env::args().skip(1);
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--seed" => match args.next().map(|seed| seed.parse()) {
+                    Some(Ok(seed)) => parsed.seed = Some(seed),
+                    _ => eprintln!("--seed expects a number"),
+                },
+                "--record" => match args.next() {
+                    Some(path) => parsed.record = Some(path.into()),
+                    None => eprintln!("--record expects a file path"),
+                },
+                "--replay" => match args.next() {
+                    Some(path) => parsed.replay = Some(path.into()),
+                    None => eprintln!("--replay expects a file path"),
+                },
+                "--headless" => match args.next() {
+                    Some(path) => parsed.headless = Some(path.into()),
+                    None => eprintln!("--headless expects a scenario file path"),
+                },
+                _ => eprintln!("Unknown argument {arg}"),
+            }
+        }
+        parsed
+    }
+}
@@ -2,51 +2,94 @@ use bevy::{
     input::{mouse::MouseButtonInput, ButtonState},
     math::Vec3Swizzles,
     prelude::{shape::Plane, *},
-    utils::HashSet,
 };
+use bevy_prototype_debug_lines::DebugLines;
 use bevy_rapier3d::prelude::*;
+use serde::{Deserialize, Serialize};
 
 use crate::{
-    common::{get_squares_from_pos, position_within_rect, ray_from_screenspace, Builds},
+    common::{
+        draw_circle, get_squares_from_pos, position_within_rect, ray_from_screenspace, Builds,
+    },
+    config::Keymap,
     gui::Sidebar,
-    towers::{BuildGrid, FulfillableSpecialTowerRecipes, JustBuilt, Tower},
-    Phase, WINDOW_HEIGHT,
+    replay::live_input,
+    save::save_load_hotkeys,
+    tower_abilities::Aura,
+    towers::{
+        BuildGrid, CombineOptions, CombineSelectedTower, CycleCombineOption,
+        FulfillableSpecialTowerRecipes, GemChances, IncreaseUpgradeChance, JustBuilt, LaserAttack,
+        MergeSelectedTower, PickSelectedTower, RandomLevel, RefineAndPickSelectedTower,
+        RemoveSelectedTower, Tower,
+    },
+    AppState, Phase,
 };
 
+/// Mouse and keyboard input on the map, turned into [`GameCommand`]s
+pub struct ControlsPlugin;
+
+impl Plugin for ControlsPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<UnderCursor>()
+            .init_resource::<CursorOverGui>()
+            .add_systems((
+                update_under_cursor,
+                show_highlight.in_set(OnUpdate(Phase::Build)),
+                show_ranges,
+                show_combine_ingredients,
+                build_on_click
+                    .in_set(OnUpdate(Phase::Build))
+                    .run_if(live_input),
+                remove_highlight.in_schedule(OnExit(Phase::Build)),
+                cursor_over_gui,
+                SelectedTower::selection.run_if(live_input),
+                SelectedTower::hotkeys.run_if(live_input),
+                SelectedTower::cycle_gems
+                    .in_set(OnUpdate(Phase::Pick))
+                    .run_if(live_input),
+                save_load_hotkeys.run_if(in_state(AppState::InGame)),
+            ));
+    }
+}
+
 pub fn update_under_cursor(
     context: Res<RapierContext>,
     mut under_cursor: ResMut<UnderCursor>,
-    windows: Query<&Window, Changed<Window>>,
+    windows: Query<&Window>,
     camera: Query<(&bevy::prelude::Camera, &Projection, &GlobalTransform), With<Camera3d>>,
 ) {
-    if let Some(cursor_pos_screen) = windows.single().cursor_position() {
+    // Recast every frame, the ground under a still cursor changes whenever the camera moves
+    let Ok(window) = windows.get_single() else {
+        return;
+    };
+    if let Some(cursor_pos_screen) = window.cursor_position() {
         let (camera, projection, camera_transform) = camera.single();
         if let Projection::Perspective(projection) = projection {
             let (from, to) = ray_from_screenspace(
                 cursor_pos_screen,
-                windows.single(),
+                window,
                 camera,
                 projection,
                 camera_transform,
                 100.0,
             );
 
-            if let Some((_, RayIntersection { point, .. })) = context.cast_ray_and_get_normal(
-                from,
-                to,
-                Real::MAX,
-                false,
-                QueryFilter::default().groups(CollisionGroups::default()),
-            ) {
-                **under_cursor = Some(point.xz());
-            } else {
-                **under_cursor = None;
-            }
+            let point = context
+                .cast_ray_and_get_normal(
+                    from,
+                    to,
+                    Real::MAX,
+                    false,
+                    QueryFilter::default().groups(CollisionGroups::default()),
+                )
+                .map(|(_, RayIntersection { point, .. })| point.xz());
+            // Only flag a change when the point moved so systems reacting to it stay idle
+            under_cursor.set_if_neq(UnderCursor(point));
         }
     }
 }
 
-#[derive(Debug, Default, Deref, DerefMut, Resource)]
+#[derive(Debug, Default, Deref, DerefMut, PartialEq, Resource)]
 pub struct UnderCursor(pub Option<Vec2>);
 
 #[derive(Component)]
@@ -129,6 +172,92 @@ pub fn remove_highlight(mut commands: Commands, highlight: Query<Entity, With<Ti
     }
 }
 
+/// Rings the towers combining the selected tower would use up
+pub fn show_combine_ingredients(
+    mut lines: ResMut<DebugLines>,
+    options: Res<CombineOptions>,
+    towers: Query<&GlobalTransform, With<Tower>>,
+) {
+    let Some(option) = options.chosen() else {
+        return;
+    };
+    for transform in towers.iter_many(&option.ingredients) {
+        let center = transform.translation().xz();
+        draw_circle(&mut lines, center, 1.2, Color::ORANGE);
+        draw_circle(&mut lines, center, 1.3, Color::ORANGE);
+    }
+}
+
+/// Draws attack and aura ranges of the selected and hovered towers, and while building the
+/// ranges the gems that could come out of the hovered tile might have
+pub fn show_ranges(
+    mut lines: ResMut<DebugLines>,
+    phase: Res<State<Phase>>,
+    under_cursor: Res<UnderCursor>,
+    cursor_over_gui: Res<CursorOverGui>,
+    random_level: Res<RandomLevel>,
+    chances: Res<GemChances>,
+    selected: Option<Res<SelectedTower>>,
+    towers: Query<
+        (
+            Entity,
+            &GlobalTransform,
+            Option<&LaserAttack>,
+            Option<&Aura>,
+        ),
+        With<Tower>,
+    >,
+) {
+    let cursor = under_cursor.filter(|_| !**cursor_over_gui);
+    let hovered = cursor.and_then(|cursor| {
+        towers
+            .iter()
+            .find(|(_, transform, ..)| transform.translation().xz().distance(cursor) <= 1.0)
+            .map(|(tower, ..)| tower)
+    });
+
+    for (tower, transform, attack, aura) in &towers {
+        if Some(tower) != hovered && selected.as_ref().map(|selected| selected.tower) != Some(tower)
+        {
+            continue;
+        }
+        let center = transform.translation().xz();
+        if let Some(attack) = attack {
+            draw_circle(&mut lines, center, attack.range, attack.color);
+        }
+        if let Some(aura) = aura {
+            draw_circle(&mut lines, center, aura.range, Color::ORANGE);
+        }
+    }
+
+    if let (Phase::Build, Some(cursor), None) = (&phase.0, cursor, hovered) {
+        // Towers are built centered on the corner the cursor is closest to
+        let center = cursor.ceil();
+        let qualities = chances.quality_weights(**random_level);
+        for &(typ, _) in &chances.types {
+            let ranges = qualities.iter().filter_map(|(quality, _)| {
+                LaserAttack::try_from(Tower::Gem {
+                    typ,
+                    quality: *quality,
+                })
+                .ok()
+                .map(|attack| attack.range)
+            });
+            let (min, max) = ranges.fold((f32::MAX, 0_f32), |(min, max), range| {
+                (min.min(range), max.max(range))
+            });
+            draw_circle(&mut lines, center, min, typ.into());
+            if max > min {
+                draw_circle(&mut lines, center, max, typ.into());
+            }
+        }
+        // Configured tables can list qualities in any order
+        if let Some(best) = qualities.iter().map(|(quality, _)| quality).max() {
+            draw_circle(&mut lines, center, Aura::opal(*best).range, Color::ORANGE);
+        }
+    }
+}
+
 #[derive(Resource, Default, Deref, DerefMut)]
 pub struct CursorOverGui(bool);
 
@@ -137,12 +266,15 @@ pub fn cursor_over_gui(
     gui: Query<(&Node, &GlobalTransform), With<Sidebar>>,
     windows: Query<&Window, Changed<Window>>,
 ) {
+    let Ok(window) = windows.get_single() else {
+        return;
+    };
     for (node, transform) in &gui {
-        if let Some(cursor_pos_screen) = windows.single().cursor_position() {
+        if let Some(cursor_pos_screen) = window.cursor_position() {
             **cursor_over_gui = position_within_rect(
                 Vec2 {
                     x: cursor_pos_screen.x,
-                    y: WINDOW_HEIGHT - cursor_pos_screen.y,
+                    y: window.height() - cursor_pos_screen.y,
                 },
                 node.size(),
                 transform.translation().xy(),
@@ -151,24 +283,91 @@ pub fn cursor_over_gui(
     }
 }
 
+/// Everything a player can do that changes the game, independent of how the input was given
+#[derive(Clone, Serialize, Deserialize)]
+pub enum GameCommand {
+    Build(UVec2),
+    Select(Option<Vec2>),
+    Pick,
+    RefineAndPick,
+    Merge,
+    Remove,
+    Combine,
+    CycleCombineOption,
+    IncreaseUpgradeChance,
+}
+
+impl GameCommand {
+    pub fn dispatch(
+        mut commands: EventReader<GameCommand>,
+        mut builds: EventWriter<BuildTower>,
+        mut selects: EventWriter<SelectTower>,
+        mut picks: EventWriter<PickSelectedTower>,
+        mut refines: EventWriter<RefineAndPickSelectedTower>,
+        mut merges: EventWriter<MergeSelectedTower>,
+        mut removes: EventWriter<RemoveSelectedTower>,
+        mut combines: EventWriter<CombineSelectedTower>,
+        mut combine_cycles: EventWriter<CycleCombineOption>,
+        mut upgrades: EventWriter<IncreaseUpgradeChance>,
+    ) {
+        for command in commands.iter() {
+            match command {
+                GameCommand::Build(tile) => builds.send(BuildTower { tile: *tile }),
+                GameCommand::Select(pos) => selects.send(SelectTower(*pos)),
+                GameCommand::Pick => picks.send(PickSelectedTower),
+                GameCommand::RefineAndPick => refines.send(RefineAndPickSelectedTower),
+                GameCommand::Merge => merges.send(MergeSelectedTower),
+                GameCommand::Remove => removes.send(RemoveSelectedTower),
+                GameCommand::Combine => combines.send(CombineSelectedTower),
+                GameCommand::CycleCombineOption => combine_cycles.send(CycleCombineOption),
+                GameCommand::IncreaseUpgradeChance => upgrades.send(IncreaseUpgradeChance),
+            }
+        }
+    }
+}
+
+impl From<PickSelectedTower> for GameCommand {
+    fn from(_: PickSelectedTower) -> Self {
+        GameCommand::Pick
+    }
+}
+
+impl From<RefineAndPickSelectedTower> for GameCommand {
+    fn from(_: RefineAndPickSelectedTower) -> Self {
+        GameCommand::RefineAndPick
+    }
+}
+
+impl From<MergeSelectedTower> for GameCommand {
+    fn from(_: MergeSelectedTower) -> Self {
+        GameCommand::Merge
+    }
+}
+
+impl From<RemoveSelectedTower> for GameCommand {
+    fn from(_: RemoveSelectedTower) -> Self {
+        GameCommand::Remove
+    }
+}
+
+impl From<CombineSelectedTower> for GameCommand {
+    fn from(_: CombineSelectedTower) -> Self {
+        GameCommand::Combine
+    }
+}
+
+impl From<CycleCombineOption> for GameCommand {
+    fn from(_: CycleCombineOption) -> Self {
+        GameCommand::CycleCombineOption
+    }
+}
+
 pub fn build_on_click(
-    mut commands: Commands,
     mut mouse: EventReader<MouseButtonInput>,
-    mut builds: ResMut<Builds>,
-    mut next_phase: ResMut<NextState<Phase>>,
-    mut meshes: ResMut<Assets<Mesh>>,
-    mut mats: ResMut<Assets<StandardMaterial>>,
-    mut build_grid: ResMut<BuildGrid>,
-    phase: Res<State<Phase>>,
+    mut commands: EventWriter<GameCommand>,
     cursor_pos: Res<UnderCursor>,
     cursor_over_gui: Res<CursorOverGui>,
 ) {
-    // If there are no more builds and the current phase is Build, change phase
-    if **builds == 0 && phase.0 == Phase::Build {
-        next_phase.set(Phase::Pick);
-        return;
-    }
-
     if **cursor_over_gui {
         return;
     }
@@ -180,40 +379,60 @@ pub fn build_on_click(
         } = event
         {
             if let Some(cursor_pos) = **cursor_pos {
-                let pos = Vec2::new(cursor_pos.x.ceil(), cursor_pos.y.ceil())
-                    .extend(1.0)
-                    .xzy();
                 #[allow(clippy::cast_sign_loss)]
-                let positions = get_squares_from_pos(pos.xz())
-                    .map(|pos| UVec2::new((pos.x - 0.5) as u32, (pos.y - 0.5) as u32));
-                if build_grid
-                    .intersection(&positions.iter().copied().collect::<HashSet<_>>())
-                    .count()
-                    > 0
-                {
-                    // Attempted to build on occupied square
-                    continue;
-                }
-                for pos in &positions {
-                    build_grid.insert(*pos);
-                }
+                commands.send(GameCommand::Build(UVec2::new(
+                    cursor_pos.x.ceil() as u32,
+                    cursor_pos.y.ceil() as u32,
+                )));
+            }
+        }
+    }
+}
 
-                let mut color: StandardMaterial =
-                    Color::rgba(fastrand::f32(), fastrand::f32(), fastrand::f32(), 0.5).into();
-                color.alpha_mode = AlphaMode::Add;
-                commands.spawn((
-                    PbrBundle {
-                        mesh: meshes.add(Tower::Dirt.into()),
-                        material: mats.add(Color::ORANGE_RED.into()),
-                        transform: Transform::from_xyz(pos.x, Tower::Dirt.get_y_offset(), pos.z),
-                        ..default()
-                    },
-                    JustBuilt,
-                    Tower::Dirt,
-                ));
+/// Builds a dirt tower centered on the corner of `tile`, covering the four squares around it
+pub struct BuildTower {
+    pub tile: UVec2,
+}
+
+impl BuildTower {
+    pub fn build(
+        mut commands: Commands,
+        mut events: EventReader<BuildTower>,
+        mut builds: ResMut<Builds>,
+        mut next_phase: ResMut<NextState<Phase>>,
+        mut meshes: ResMut<Assets<Mesh>>,
+        mut mats: ResMut<Assets<StandardMaterial>>,
+        mut build_grid: ResMut<BuildGrid>,
+        phase: Res<State<Phase>>,
+    ) {
+        // If there are no more builds and the current phase is Build, change phase
+        if **builds == 0 && phase.0 == Phase::Build {
+            next_phase.set(Phase::Pick);
+            return;
+        }
 
-                **builds -= 1;
+        for BuildTower { tile } in events.iter() {
+            if **builds == 0 {
+                break;
             }
+            if !build_grid.occupy(*tile) {
+                // Attempted to build on occupied square
+                continue;
+            }
+            let pos = tile.as_vec2();
+
+            commands.spawn((
+                PbrBundle {
+                    mesh: meshes.add(Tower::Dirt.into()),
+                    material: mats.add(Color::ORANGE_RED.into()),
+                    transform: Transform::from_xyz(pos.x, Tower::Dirt.get_y_offset(), pos.y),
+                    ..default()
+                },
+                JustBuilt,
+                Tower::Dirt,
+            ));
+
+            **builds -= 1;
         }
     }
 }
@@ -226,17 +445,89 @@ pub struct SelectedTower {
     pub refinable: bool,
     pub removable: bool,
     pub combinable: bool,
+    /// There are identical gems among this round's to merge with
+    pub mergeable: bool,
 }
 
 impl SelectedTower {
+    /// Sends the same commands as the sidebar buttons, under the same conditions they are shown
+    pub fn hotkeys(
+        keys: Res<Input<KeyCode>>,
+        keymap: Res<Keymap>,
+        phase: Res<State<Phase>>,
+        selected: Option<Res<SelectedTower>>,
+        mut commands: EventWriter<GameCommand>,
+    ) {
+        if keys.just_pressed(keymap.upgrade_chance) {
+            commands.send(GameCommand::IncreaseUpgradeChance);
+        }
+        let Some(selected) = selected else {
+            return;
+        };
+        let phase = &phase.0;
+        if keys.just_pressed(keymap.pick) && *phase == Phase::Pick && selected.pickable {
+            commands.send(GameCommand::Pick);
+        }
+        if keys.just_pressed(keymap.refine_and_pick) && *phase == Phase::Pick && selected.refinable
+        {
+            commands.send(GameCommand::RefineAndPick);
+        }
+        if keys.just_pressed(keymap.merge) && *phase == Phase::Pick && selected.mergeable {
+            commands.send(GameCommand::Merge);
+        }
+        if keys.just_pressed(keymap.remove)
+            && matches!(phase, Phase::Pick | Phase::Build)
+            && selected.removable
+        {
+            commands.send(GameCommand::Remove);
+        }
+        if keys.just_pressed(keymap.combine) && selected.combinable {
+            commands.send(GameCommand::Combine);
+        }
+        if keys.just_pressed(keymap.next_combination) && selected.combinable {
+            commands.send(GameCommand::CycleCombineOption);
+        }
+    }
+
+    /// Steps the selection through this round's gems, in an order that doesn't follow the build
+    /// order but stays the same for the whole round
+    pub fn cycle_gems(
+        keys: Res<Input<KeyCode>>,
+        keymap: Res<Keymap>,
+        selected: Option<Res<SelectedTower>>,
+        mut commands: EventWriter<GameCommand>,
+        gems: Query<(Entity, &GlobalTransform), With<JustBuilt>>,
+    ) {
+        let forward = match (
+            keys.just_pressed(keymap.next_gem),
+            keys.just_pressed(keymap.previous_gem),
+        ) {
+            (true, false) => true,
+            (false, true) => false,
+            _ => return,
+        };
+        let mut gems = gems.iter().collect::<Vec<_>>();
+        if gems.is_empty() {
+            return;
+        }
+        // Entities don't change during a round, so sorting by them keeps the order stable
+        gems.sort_by_key(|(gem, _)| *gem);
+        let next = match selected
+            .and_then(|selected| gems.iter().position(|(gem, _)| *gem == selected.tower))
+        {
+            Some(current) if forward => (current + 1) % gems.len(),
+            Some(current) => (current + gems.len() - 1) % gems.len(),
+            None if forward => 0,
+            None => gems.len() - 1,
+        };
+        commands.send(GameCommand::Select(Some(gems[next].1.translation().xz())));
+    }
+
     pub fn selection(
-        mut commands: Commands,
         mut mouse: EventReader<MouseButtonInput>,
+        mut commands: EventWriter<GameCommand>,
         under_cursor: Res<UnderCursor>,
         cursor_over_gui: Res<CursorOverGui>,
-        fulfillable_recipes: Res<FulfillableSpecialTowerRecipes>,
-        towers: Query<(Entity, &GlobalTransform, &Tower)>,
-        just_built: Query<(), With<JustBuilt>>,
     ) {
         for event in mouse.iter() {
             if let MouseButtonInput {
@@ -247,39 +538,66 @@ impl SelectedTower {
                 if **cursor_over_gui {
                     continue;
                 }
-                if let Some(cursor_pos) = **under_cursor {
-                    let mut picked_tower = None;
-                    for (entity, transform, tower) in &towers {
-                        if transform.translation().xz().distance(cursor_pos) <= 1.0 {
-                            picked_tower = Some((entity, tower));
-                        }
-                    }
+                commands.send(GameCommand::Select(**under_cursor));
+            }
+        }
+    }
+}
 
-                    if let Some((picked_tower, typ)) = picked_tower {
-                        let just_built = just_built.contains(picked_tower);
-                        let pickable = just_built && *typ != Tower::Dirt;
-                        commands.insert_resource(SelectedTower {
-                            tower: picked_tower,
-                            pickable,
-                            refinable: towers
-                                .iter()
-                                .filter(|(_, _, tower)| *typ != Tower::Dirt && *tower == typ)
-                                .count()
-                                >= 2,
-                            removable: !just_built
-                                && towers
-                                    .get_component::<Tower>(picked_tower)
-                                    .is_ok_and(|tower| *tower == Tower::Dirt),
-                            combinable: fulfillable_recipes
-                                .iter()
-                                .any(|recipe| recipe.ingredients.contains(typ)),
-                        });
-                    } else {
-                        commands.remove_resource::<SelectedTower>();
+/// Selects the tower at the given ground position, or clears the selection if there is none
+pub struct SelectTower(pub Option<Vec2>);
+
+impl SelectTower {
+    pub fn select(
+        mut commands: Commands,
+        mut events: EventReader<SelectTower>,
+        fulfillable_recipes: Res<FulfillableSpecialTowerRecipes>,
+        towers: Query<(Entity, &GlobalTransform, &Tower)>,
+        just_built: Query<(), With<JustBuilt>>,
+    ) {
+        for SelectTower(position) in events.iter() {
+            if let Some(cursor_pos) = *position {
+                let mut picked_tower = None;
+                for (entity, transform, tower) in &towers {
+                    if transform.translation().xz().distance(cursor_pos) <= 1.0 {
+                        picked_tower = Some((entity, tower));
                     }
+                }
+
+                if let Some((picked_tower, typ)) = picked_tower {
+                    let mergeable = just_built.contains(picked_tower)
+                        && typ.get_refine().is_ok()
+                        && towers
+                            .iter()
+                            .filter(|(entity, _, tower)| {
+                                just_built.contains(*entity) && *tower == typ
+                            })
+                            .count()
+                            >= 2;
+                    let just_built = just_built.contains(picked_tower);
+                    let pickable = just_built && *typ != Tower::Dirt;
+                    commands.insert_resource(SelectedTower {
+                        tower: picked_tower,
+                        pickable,
+                        refinable: towers
+                            .iter()
+                            .filter(|(_, _, tower)| *typ != Tower::Dirt && *tower == typ)
+                            .count()
+                            >= 2,
+                        removable: !just_built
+                            && towers
+                                .get_component::<Tower>(picked_tower)
+                                .is_ok_and(|tower| *tower == Tower::Dirt),
+                        combinable: fulfillable_recipes
+                            .iter()
+                            .any(|recipe| recipe.ingredients.contains(typ)),
+                        mergeable,
+                    });
                 } else {
                     commands.remove_resource::<SelectedTower>();
                 }
+            } else {
+                commands.remove_resource::<SelectedTower>();
             }
         }
     }
@@ -1,23 +1,96 @@
+use std::fmt::Display;
+
 use bevy::{
     prelude::{shape::Cube, *},
-    utils::HashMap,
+    utils::{HashMap, HashSet},
 };
 use seldom_map_nav::prelude::*;
 
 use crate::{
-    common::{CreepPos, TrackWorldObjectToScreenPosition},
+    common::{update_creep_position, CreepPos, TrackWorldObjectToScreenPosition},
     progress_bar::ProgressBar,
     towers::Hits,
-    CurrentLevel, Phase, CREEP_CLEARANCE, MAP_WIDTH, RESOLUTION, WINDOW_HEIGHT,
+    CurrentLevel, Phase, CREEP_CLEARANCE, MAP_WIDTH,
 };
 
 const CREEP_BASE_SPEED: f32 = 1.;
+const CREEP_SPAWN: Vec2 = Vec2::new(0.5, MAP_WIDTH as f32 - 1.);
+const CREEP_GOAL: Vec2 = Vec2::new(0.5 + MAP_WIDTH as f32 - 1., 0.5);
+
+/// Creeps walking through the maze and everything that damages and kills them
+pub struct CreepsPlugin;
+
+impl Plugin for CreepsPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<Hit>()
+            .add_event::<Dead>()
+            .add_event::<Leaked>()
+            .add_event::<DamageEvent>()
+            .add_event::<DamageDealt>()
+            .configure_sets(
+                (
+                    DamageSet::Attack,
+                    DamageSet::OnHit,
+                    DamageSet::Apply,
+                    DamageSet::Resolve,
+                )
+                    .chain(),
+            )
+            .add_systems((
+                Hit::consume.in_set(DamageSet::OnHit),
+                DamageEvent::apply.in_set(DamageSet::Apply),
+                Dead::death.in_set(DamageSet::Resolve),
+                CreepSpawner::spawn.in_set(OnUpdate(Phase::Spawn)),
+                // Before any damage so a creep that leaks can't also die in the same frame
+                Creep::leak
+                    .before(DamageSet::Attack)
+                    .in_set(OnUpdate(Phase::Spawn)),
+                CreepSpawner::reset_amount_system.in_schedule(OnEnter(Phase::Spawn)),
+                Slow::change.in_set(OnUpdate(Phase::Spawn)),
+                update_creep_position,
+            ));
+    }
+}
 
 #[derive(Component)]
 pub struct Creep {
     pub typ: CreepType,
 }
 
+impl Creep {
+    /// Removes creeps that made it all the way to the goal
+    pub fn leak(
+        mut commands: Commands,
+        mut writer: EventWriter<Leaked>,
+        creeps: Query<(Entity, &CreepPos), With<Creep>>,
+        bars: Query<(&ProgressBar, &Parent)>,
+    ) {
+        for (creep, pos) in &creeps {
+            if pos.pos.distance(CREEP_GOAL) <= CREEP_CLEARANCE {
+                writer.send(Leaked(creep));
+                despawn_creep(&mut commands, creep, &bars);
+            }
+        }
+    }
+}
+
+/// Despawns a creep along with its health bar
+fn despawn_creep(commands: &mut Commands, creep: Entity, bars: &Query<(&ProgressBar, &Parent)>) {
+    if let Some(entity) = commands.get_entity(creep) {
+        entity.despawn_recursive();
+        for (bar, parent) in bars.iter() {
+            if bar.target == creep {
+                if let Some(entity) = commands.get_entity(**parent) {
+                    entity.despawn_recursive();
+                }
+            }
+        }
+    }
+}
+
+#[derive(Deref, DerefMut)]
+pub struct Leaked(pub Entity);
+
 pub struct Hit {
     pub source: Entity,
     pub target: Entity,
@@ -25,22 +98,112 @@ pub struct Hit {
 }
 
 impl Hit {
-    pub fn consume(
-        mut reader: EventReader<Hit>,
-        mut writer: EventWriter<Dead>,
-        mut targets: Query<&mut HitPoints>,
+    pub fn consume(mut reader: EventReader<Hit>, mut writer: EventWriter<DamageEvent>) {
+        for hit in &mut reader {
+            writer.send(DamageEvent {
+                source: hit.source,
+                target: hit.target,
+                amount: hit.value,
+                kind: DamageKind::Attack,
+            });
+        }
+    }
+}
+
+/// Order damage flows through each frame, anything that hurts creeps sends a [`DamageEvent`] in
+/// [`DamageSet::OnHit`] and only [`DamageEvent::apply`] touches [`HitPoints`]
+#[derive(SystemSet, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum DamageSet {
+    /// Towers fire and send [`Hit`]s
+    Attack,
+    /// Hits and effects turn into [`DamageEvent`]s
+    OnHit,
+    /// Damage is subtracted from [`HitPoints`]
+    Apply,
+    /// Reactions to [`DamageDealt`] and [`Dead`]
+    Resolve,
+}
+
+/// Damage about to be dealt to a creep, before any clamping to its remaining hitpoints
+pub struct DamageEvent {
+    pub source: Entity,
+    pub target: Entity,
+    pub amount: u32,
+    pub kind: DamageKind,
+}
+
+impl DamageEvent {
+    pub fn apply(
+        mut events: EventReader<DamageEvent>,
+        mut leaked: EventReader<Leaked>,
+        mut dealt: EventWriter<DamageDealt>,
+        mut deaths: EventWriter<Dead>,
+        mut creeps: Query<&mut HitPoints>,
     ) {
-        for damaged in &mut reader {
-            if let Ok(mut hitpoints) = targets.get_mut(damaged.target) {
-                hitpoints.sub(damaged.value);
-                if hitpoints.dead() {
-                    writer.send(Dead(damaged.target));
+        // Creeps that leaked this frame are only despawned at the end of it
+        let leaked = leaked.iter().map(|leaked| **leaked).collect::<HashSet<_>>();
+        for event in events.iter() {
+            if leaked.contains(&event.target) {
+                continue;
+            }
+            if let Ok(mut hitpoints) = creeps.get_mut(event.target) {
+                let damage = hitpoints.damage(event);
+                if let Some(death) = damage.death() {
+                    deaths.send(death);
                 }
+                dealt.send(damage);
             }
         }
     }
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum DamageKind {
+    Attack,
+    Crit,
+    Splash,
+    Poison,
+}
+
+impl Display for DamageKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                DamageKind::Attack => "attack",
+                DamageKind::Crit => "crit",
+                DamageKind::Splash => "splash",
+                DamageKind::Poison => "poison",
+            }
+        )
+    }
+}
+
+/// How much of a tower's damage actually landed on a creep
+#[derive(Clone)]
+pub struct DamageDealt {
+    pub source: Entity,
+    pub target: Entity,
+    pub kind: DamageKind,
+    pub amount: u32,
+    /// Damage beyond what the creep had left
+    pub overkill: u32,
+    /// Whether this damage took the creep from alive to dead
+    pub killed: bool,
+}
+
+impl DamageDealt {
+    /// The death this damage caused, only ever [`Some`] once per creep
+    fn death(&self) -> Option<Dead> {
+        self.killed.then_some(Dead {
+            creep: self.target,
+            killer: self.source,
+            kind: self.kind,
+        })
+    }
+}
+
 #[derive(Component, Debug)]
 pub struct HitPoints {
     max: u32,
@@ -55,12 +218,27 @@ impl HitPoints {
         }
     }
 
-    pub fn sub(&mut self, value: u32) {
-        self.current = self.current.saturating_sub(value);
+    /// Subtracts damage, keeping track of the part that was wasted on overkill
+    fn damage(&mut self, event: &DamageEvent) -> DamageDealt {
+        let amount = event.amount.min(self.current);
+        let killed = amount > 0 && amount == self.current;
+        self.current -= amount;
+        DamageDealt {
+            source: event.source,
+            target: event.target,
+            kind: event.kind,
+            amount,
+            overkill: event.amount - amount,
+            killed,
+        }
+    }
+
+    pub fn current(&self) -> u32 {
+        self.current
     }
 
-    pub fn dead(&self) -> bool {
-        self.current == 0
+    pub fn max(&self) -> u32 {
+        self.max
     }
 
     fn ratio(&self) -> f32 {
@@ -69,11 +247,18 @@ impl HitPoints {
 
     pub fn spawn_health_bars(
         mut commands: Commands,
+        windows: Query<&Window>,
+        ui_scale: Res<UiScale>,
         hitpoints: Query<(Entity, &HitPoints), Added<HitPoints>>,
     ) {
+        let Ok(window) = windows.get_single() else {
+            return;
+        };
+        // Start in the middle of the screen until the bar gets moved onto its creep
+        let center = Vec2::new(window.width(), window.height()) / 2. / ui_scale.scale as f32;
         for (entity, hitpoints) in &hitpoints {
             let bar = ProgressBar::spawn(
-                (WINDOW_HEIGHT * RESOLUTION / 2.0, WINDOW_HEIGHT / 2.0).into(),
+                center,
                 Color::GREEN,
                 Color::RED,
                 hitpoints.ratio(),
@@ -86,6 +271,7 @@ impl HitPoints {
                 .insert(TrackWorldObjectToScreenPosition {
                     target: entity,
                     offset: Vec2::new(0.0, 21.0),
+                    last_position: None,
                 });
 
             commands.entity(entity).insert(UpdateHitpointsBar(bar));
@@ -155,8 +341,13 @@ impl HitPoints {
 #[derive(Component)]
 struct UpdateHitpointsBar(Entity);
 
-#[derive(Deref, DerefMut)]
-pub struct Dead(pub Entity);
+#[derive(Clone)]
+pub struct Dead {
+    pub creep: Entity,
+    /// Tower that dealt the killing blow
+    pub killer: Entity,
+    pub kind: DamageKind,
+}
 
 impl Dead {
     pub fn death(
@@ -164,17 +355,8 @@ impl Dead {
         mut reader: EventReader<Dead>,
         bars: Query<(&ProgressBar, &Parent)>,
     ) {
-        for Dead(dead) in reader.iter() {
-            if let Some(entity) = commands.get_entity(*dead) {
-                entity.despawn_recursive();
-                for (bar, parent) in bars.iter() {
-                    if bar.target == *dead {
-                        if let Some(entity) = commands.get_entity(**parent) {
-                            entity.despawn_recursive();
-                        }
-                    }
-                }
-            }
+        for Dead { creep, .. } in reader.iter() {
+            despawn_creep(&mut commands, *creep, &bars);
         }
     }
 }
@@ -224,12 +406,12 @@ impl CreepSpawner {
                     mesh: meshes.add(Cube { size: 0.5 }.into()),
                     material: mats.add(Color::BLACK.into()),
                     transform: Transform::from_xyz(
-                        0.5,
+                        CREEP_SPAWN.x,
                         match typ {
                             CreepType::Ground => 0.25,
                             CreepType::Flying => 1.25,
                         },
-                        MAP_WIDTH as f32 - 1.,
+                        CREEP_SPAWN.y,
                     ),
                     ..default()
                 },
@@ -240,15 +422,13 @@ impl CreepSpawner {
                         navmesh,
                         CREEP_CLEARANCE,
                         None,
-                        PathTarget::Static(Vec2::new(0.5 + MAP_WIDTH as f32 - 1., 0.5)),
+                        PathTarget::Static(CREEP_GOAL),
                         NavQuery::Accuracy,
                         NavPathMode::Accuracy,
                     ),
                     nav: Nav::new(CREEP_BASE_SPEED),
                 },
-                CreepPos {
-                    pos: Vec2::new(0.5, MAP_WIDTH as f32 - 1.),
-                },
+                CreepPos { pos: CREEP_SPAWN },
                 Name::new("Creep"),
                 Slow::default(),
             ));
@@ -0,0 +1,110 @@
+use bevy::{math::Vec3Swizzles, prelude::*};
+use bevy_debug_grid::DebugGridPlugin;
+use bevy_inspector_egui::quick::{ResourceInspectorPlugin, WorldInspectorPlugin};
+use bevy_prototype_debug_lines::DebugLines;
+use seldom_map_nav::prelude::*;
+
+use crate::{
+    config::{Config, Keymap},
+    towers::{BuildGrid, FulfillableSpecialTowerRecipes, NavmeshTiles, Target},
+    CREEP_CLEARANCE, MAP_HEIGHT, MAP_WIDTH,
+};
+
+/// Inspectors, the floor grid and the debug overlay, only built with the `dev-tools` feature
+pub struct DevToolsPlugin;
+
+impl Plugin for DevToolsPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugin(WorldInspectorPlugin::new())
+            .add_plugin(DebugGridPlugin::with_floor_grid())
+            .register_type::<FulfillableSpecialTowerRecipes>()
+            .add_plugin(ResourceInspectorPlugin::<FulfillableSpecialTowerRecipes>::default())
+            .add_systems((
+                toggle_overlay,
+                draw_navmesh.run_if(overlay_enabled),
+                draw_build_grid.run_if(overlay_enabled),
+                draw_targets.run_if(overlay_enabled),
+            ));
+    }
+}
+
+fn overlay_enabled(config: Res<Config>) -> bool {
+    config.debug_overlay
+}
+
+fn toggle_overlay(keys: Res<Input<KeyCode>>, keymap: Res<Keymap>, mut config: ResMut<Config>) {
+    if keys.just_pressed(keymap.debug_overlay) {
+        config.debug_overlay = !config.debug_overlay;
+    }
+}
+
+/// Draws a rectangle on the ground for a single frame
+fn draw_rect(lines: &mut DebugLines, min: Vec2, max: Vec2, color: Color) {
+    let corners = [min, Vec2::new(max.x, min.y), max, Vec2::new(min.x, max.y)]
+        .map(|corner| corner.extend(0.06).xzy());
+    for index in 0..corners.len() {
+        lines.line_colored(
+            corners[index],
+            corners[(index + 1) % corners.len()],
+            0.,
+            color,
+        );
+    }
+}
+
+/// Outlines the area creeps can walk in on the navmesh they are pathing on, solid squares grow by
+/// the creep clearance the navmesh is generated with. Drawn from the tiles the navmesh was last
+/// generated from, so towers placed since then don't show up until it is rebuilt
+fn draw_navmesh(mut lines: ResMut<DebugLines>, navmeshes: Query<&NavmeshTiles, With<Navmeshes>>) {
+    let Ok(tiles) = navmeshes.get_single() else {
+        return;
+    };
+    let map = Vec2::new(MAP_WIDTH as f32, MAP_HEIGHT as f32);
+    draw_rect(
+        &mut lines,
+        Vec2::splat(CREEP_CLEARANCE),
+        map - CREEP_CLEARANCE,
+        Color::CYAN,
+    );
+    for square in tiles.iter() {
+        let min = square.as_vec2();
+        draw_rect(
+            &mut lines,
+            min - CREEP_CLEARANCE,
+            min + 1. + CREEP_CLEARANCE,
+            Color::CYAN,
+        );
+    }
+}
+
+/// Crosses out every occupied square of the [`BuildGrid`]
+fn draw_build_grid(mut lines: ResMut<DebugLines>, build_grid: Res<BuildGrid>) {
+    for square in build_grid.iter() {
+        let min = square.as_vec2();
+        let max = min + 1.;
+        draw_rect(&mut lines, min, max, Color::YELLOW);
+        lines.line_colored(
+            min.extend(0.06).xzy(),
+            max.extend(0.06).xzy(),
+            0.,
+            Color::YELLOW,
+        );
+    }
+}
+
+/// Connects every tower with what it is shooting at
+fn draw_targets(
+    mut lines: ResMut<DebugLines>,
+    towers: Query<(&GlobalTransform, &Target)>,
+    creeps: Query<&GlobalTransform>,
+) {
+    for (tower, target) in &towers {
+        let targets = match target {
+            Target::Single(target) => target.as_slice(),
+            Target::Multiple(targets) => targets.as_slice(),
+        };
+        for creep in creeps.iter_many(targets) {
+            lines.line_colored(tower.translation(), creep.translation(), 0., Color::RED);
+        }
+    }
+}
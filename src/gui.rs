@@ -1,35 +1,105 @@
 use std::marker::PhantomData;
 
-use bevy::prelude::*;
+use bevy::{
+    input::mouse::{MouseScrollUnit, MouseWheel},
+    prelude::*,
+};
 
 use crate::{
-    controls::SelectedTower,
+    common::Builds,
+    common::{Fadeout, MovingTo, RawTime, TrackWorldObjectToScreenPosition},
+    config::{Config, Keymap},
+    controls::{GameCommand, SelectedTower},
+    creeps::{
+        Creep, CreepSpawner, CreepType, DamageDealt, DamageKind, DamageSet, HitPoints, Leaked,
+    },
+    next_level,
+    replay::live_input,
+    save::SaveError,
+    speed::GameSpeed,
+    stats::TowerStats,
     towers::{
-        CombineSelectedTower, Cooldown, LaserAttack, PickSelectedTower, RandomLevel,
-        RefineAndPickSelectedTower, RemoveSelectedTower,
+        CombineOptions, CombineSelectedTower, Cooldown, CycleCombineOption,
+        FulfillableSpecialTowerRecipes, GameError, GemChances, GemDroughts, LaserAttack,
+        MergeSelectedTower, PickSelectedTower, RandomLevel, RefineAndPickSelectedTower,
+        RemoveSelectedTower, SpecialTowerCombined, SpecialTowerRecipes, Tower,
     },
-    Phase,
+    CurrentLevel, Phase, WINDOW_HEIGHT,
 };
 
 pub struct GameGuiPlugin;
 
 impl Plugin for GameGuiPlugin {
     fn build(&self, app: &mut App) {
-        app.add_startup_systems((SidebarFullscreen::spawn,))
+        app.add_event::<Notification>()
+            .add_startup_systems((
+                SidebarFullscreen::spawn,
+                Toast::spawn_stack,
+                RecipeBook::spawn,
+            ))
             .add_systems((
-                event_buttons::<PickSelectedTower>.in_set(OnUpdate(Phase::Pick)),
-                event_buttons::<RefineAndPickSelectedTower>.in_set(OnUpdate(Phase::Pick)),
-                event_buttons::<RemoveSelectedTower>.in_set(OnUpdate(Phase::Pick)),
-                event_buttons::<RemoveSelectedTower>.in_set(OnUpdate(Phase::Build)),
-                event_buttons::<CombineSelectedTower>.in_set(OnUpdate(Phase::Pick)),
-                event_buttons::<CombineSelectedTower>.in_set(OnUpdate(Phase::Spawn)),
-                UpgradeChanceButton::interaction,
+                event_buttons::<PickSelectedTower>
+                    .in_set(OnUpdate(Phase::Pick))
+                    .run_if(live_input),
+                event_buttons::<RefineAndPickSelectedTower>
+                    .in_set(OnUpdate(Phase::Pick))
+                    .run_if(live_input),
+                event_buttons::<MergeSelectedTower>
+                    .in_set(OnUpdate(Phase::Pick))
+                    .run_if(live_input),
+                event_buttons::<RemoveSelectedTower>
+                    .in_set(OnUpdate(Phase::Pick))
+                    .run_if(live_input),
+                event_buttons::<RemoveSelectedTower>
+                    .in_set(OnUpdate(Phase::Build))
+                    .run_if(live_input),
+                event_buttons::<CombineSelectedTower>.run_if(live_input),
+                UpgradeChanceButton::interaction.run_if(live_input),
                 UpgradeChanceButton::update,
                 SelectedText::on_update,
                 show_pickable_button,
                 show_refine_and_pick_button,
                 show_remove_button,
                 show_combine_button,
+                WaveSummary::spawn
+                    .in_schedule(OnExit(Phase::Spawn))
+                    .after(next_level),
+                WaveSummary::despawn.in_schedule(OnEnter(Phase::Spawn)),
+            ))
+            .add_systems((
+                DamageNumber::spawn.after(DamageSet::Resolve),
+                DamageNumber::float.after(TrackWorldObjectToScreenPosition::track),
+                DamageNumber::toggle,
+                scale_ui,
+                Fadeout::fadeout,
+                TrackWorldObjectToScreenPosition::track,
+                MovingTo::move_to,
+                HitPoints::spawn_health_bars,
+                HitPoints::update_health_bars,
+                SpeedButton::interaction.run_if(live_input),
+                SpeedButton::update,
+                WaveHud::update,
+            ))
+            .add_systems((
+                Notification::from_errors,
+                Notification::from_save_errors,
+                Notification::level_started.in_schedule(OnEnter(Phase::Spawn)),
+                Notification::leaks,
+                Notification::special_towers,
+                Toast::spawn
+                    .after(Notification::from_errors)
+                    .after(Notification::from_save_errors),
+                Toast::fade,
+                MessageLog::push
+                    .after(Notification::from_errors)
+                    .after(Notification::from_save_errors),
+                MessageLog::scroll,
+                RecipeBook::toggle,
+                RecipeBook::update,
+                event_buttons::<CycleCombineOption>.run_if(live_input),
+                CombineOptionText::update,
+                show_merge_button,
+                GemOddsText::update,
             ));
     }
 }
@@ -99,6 +169,20 @@ impl SidebarFullscreen {
             })
             .id();
 
+        let wave_hud = commands
+            .spawn((
+                TextBundle {
+                    text: Text::default(),
+                    style: Style {
+                        align_self: AlignSelf::Start,
+                        ..default()
+                    },
+                    ..default()
+                },
+                WaveHud,
+            ))
+            .id();
+
         let selected_text = commands
             .spawn((
                 TextBundle {
@@ -168,6 +252,19 @@ impl SidebarFullscreen {
                     event: EventButton::<RefineAndPickSelectedTower>::new(),
                 },))
                 .id(),
+            commands
+                .spawn((EventButtonBundle {
+                    button: ButtonBundle {
+                        style: Style {
+                            size: Size::all(Val::Px(50.)),
+                            ..default()
+                        },
+                        background_color: Color::VIOLET.into(),
+                        ..default()
+                    },
+                    event: EventButton::<MergeSelectedTower>::new(),
+                },))
+                .id(),
             commands
                 .spawn((EventButtonBundle {
                     button: ButtonBundle {
@@ -212,15 +309,153 @@ impl SidebarFullscreen {
                 .id(),
         ];
 
+        let gem_odds_text = commands
+            .spawn((
+                TextBundle::from_section(
+                    "",
+                    TextStyle {
+                        font: ass.load("Mukta-Regular.ttf"),
+                        font_size: 20.,
+                        color: Color::BLACK,
+                    },
+                )
+                .with_style(Style {
+                    align_self: AlignSelf::Center,
+                    ..default()
+                }),
+                GemOddsText,
+            ))
+            .id();
+
+        let combine_option_text = commands
+            .spawn((
+                TextBundle::from_section(
+                    "",
+                    TextStyle {
+                        font: ass.load("Mukta-Regular.ttf"),
+                        font_size: 24.,
+                        color: Color::BLACK,
+                    },
+                ),
+                CombineOptionText,
+            ))
+            .id();
+        let combine_option_button = commands
+            .spawn((EventButtonBundle {
+                button: ButtonBundle {
+                    style: Style {
+                        size: Size::new(Val::Px(250.), Val::Px(36.)),
+                        align_self: AlignSelf::Center,
+                        align_items: AlignItems::Center,
+                        justify_content: JustifyContent::Center,
+                        display: Display::None,
+                        ..default()
+                    },
+                    background_color: Color::ORANGE.into(),
+                    visibility: Visibility::Hidden,
+                    ..default()
+                },
+                event: EventButton::<CycleCombineOption>::new(),
+            },))
+            .add_child(combine_option_text)
+            .id();
+
+        let speed_bar = commands
+            .spawn((NodeBundle {
+                style: Style {
+                    flex_direction: FlexDirection::Row,
+                    justify_content: JustifyContent::Center,
+                    ..default()
+                },
+                ..default()
+            },))
+            .id();
+
+        let speed_buttons = [
+            (SpeedButton::Pause, SpeedButton::PAUSE_TEXT.to_string()),
+            (SpeedButton::Speed, SpeedButton::speed_text(1.)),
+        ]
+        .map(|(button, label)| {
+            let text = commands
+                .spawn((
+                    TextBundle {
+                        text: Text::from_section(
+                            label,
+                            TextStyle {
+                                font: ass.load("Mukta-Regular.ttf"),
+                                font_size: 30.,
+                                color: Color::BLACK,
+                            },
+                        ),
+                        ..default()
+                    },
+                    SpeedButtonText(button),
+                ))
+                .id();
+            commands
+                .spawn((
+                    ButtonBundle {
+                        style: Style {
+                            size: Size::new(Val::Px(75.), Val::Px(40.)),
+                            align_items: AlignItems::Center,
+                            justify_content: JustifyContent::Center,
+                            ..default()
+                        },
+                        background_color: Color::SILVER.into(),
+                        ..default()
+                    },
+                    button,
+                ))
+                .add_child(text)
+                .id()
+        });
+
+        let message_log_list = commands
+            .spawn((
+                NodeBundle {
+                    style: Style {
+                        flex_direction: FlexDirection::Column,
+                        flex_shrink: 0.,
+                        ..default()
+                    },
+                    ..default()
+                },
+                MessageLog::default(),
+            ))
+            .id();
+        let message_log = commands
+            .spawn((
+                NodeBundle {
+                    background_color: Color::rgba(0., 0., 0., 0.3).into(),
+                    style: Style {
+                        flex_direction: FlexDirection::Column,
+                        size: Size::new(Val::Percent(100.), Val::Px(MessageLog::HEIGHT)),
+                        padding: UiRect::all(Val::Px(4.)),
+                        overflow: Overflow::Hidden,
+                        ..default()
+                    },
+                    ..default()
+                },
+                Interaction::default(),
+            ))
+            .add_child(message_log_list)
+            .id();
+
         commands.entity(full_screen).add_child(sidebar_background);
 
         commands
             .entity(sidebar_background)
             .add_child(title)
+            .add_child(wave_hud)
             .add_child(selected_text)
-            .add_child(button_bar);
+            .add_child(button_bar)
+            .add_child(gem_odds_text)
+            .add_child(combine_option_button)
+            .add_child(speed_bar)
+            .add_child(message_log);
 
         commands.entity(button_bar).push_children(&buttons);
+        commands.entity(speed_bar).push_children(&speed_buttons);
     }
 
     fn _despawn(mut commands: Commands, sidebar: Query<Entity, With<SidebarFullscreen>>) {
@@ -238,12 +473,12 @@ struct UpgradeChanceButtonText;
 
 impl UpgradeChanceButton {
     fn interaction(
-        mut random_level: ResMut<RandomLevel>,
+        mut events: EventWriter<GameCommand>,
         buttons: Query<&Interaction, (With<UpgradeChanceButton>, Changed<Interaction>)>,
     ) {
         for interaction in &buttons {
             if let Interaction::Clicked = interaction {
-                **random_level += 1;
+                events.send(GameCommand::IncreaseUpgradeChance);
             }
         }
     }
@@ -268,6 +503,171 @@ impl UpgradeChanceButton {
     }
 }
 
+/// Odds of each gem quality at the current upgrade chance level and of each gem type, with the
+/// bad luck protection included
+#[derive(Component)]
+struct GemOddsText;
+
+impl GemOddsText {
+    fn update(
+        random_level: Res<RandomLevel>,
+        chances: Res<GemChances>,
+        droughts: Res<GemDroughts>,
+        mut texts: Query<&mut Text, With<GemOddsText>>,
+    ) {
+        if !(random_level.is_changed() || chances.is_changed() || droughts.is_changed()) {
+            return;
+        }
+        let label = format!(
+            "{}\n{}",
+            Self::percentages(chances.quality_weights(**random_level)),
+            Self::percentages(&chances.type_weights(&droughts))
+        );
+        for mut text in &mut texts {
+            text.sections[0].value.clone_from(&label);
+        }
+    }
+
+    fn percentages<T: std::fmt::Debug>(weights: &[(T, u32)]) -> String {
+        let total = u64::from(GemChances::total(weights).max(1));
+        weights
+            .iter()
+            .map(|(value, weight)| format!("{value:?} {}%", u64::from(*weight) * 100 / total))
+            .collect::<Vec<_>>()
+            .join("  ")
+    }
+}
+
+/// Pauses the game or steps through the game speeds
+#[derive(Component, Clone, Copy, PartialEq, Eq)]
+enum SpeedButton {
+    Pause,
+    Speed,
+}
+
+#[derive(Component)]
+struct SpeedButtonText(SpeedButton);
+
+impl SpeedButton {
+    const PAUSE_TEXT: &str = "Pause";
+
+    fn speed_text(multiplier: f32) -> String {
+        format!("{multiplier}x")
+    }
+
+    fn interaction(
+        mut speed: ResMut<GameSpeed>,
+        buttons: Query<(&Interaction, &SpeedButton), Changed<Interaction>>,
+    ) {
+        for (interaction, button) in &buttons {
+            if let Interaction::Clicked = interaction {
+                match button {
+                    SpeedButton::Pause => speed.paused = !speed.paused,
+                    SpeedButton::Speed => speed.cycle(),
+                }
+            }
+        }
+    }
+
+    fn update(speed: Res<GameSpeed>, mut texts: Query<(&mut Text, &SpeedButtonText)>) {
+        if !speed.is_changed() {
+            return;
+        }
+        for (mut text, SpeedButtonText(button)) in &mut texts {
+            text.sections[0].value = match button {
+                SpeedButton::Pause if speed.paused => "Resume".to_string(),
+                SpeedButton::Pause => Self::PAUSE_TEXT.to_string(),
+                SpeedButton::Speed => Self::speed_text(speed.multiplier()),
+            };
+        }
+    }
+}
+
+/// Which of the [`CombineOptions`] combining would go with, clicking it moves on to the next one
+#[derive(Component)]
+struct CombineOptionText;
+
+impl CombineOptionText {
+    fn update(
+        options: Res<CombineOptions>,
+        mut texts: Query<&mut Text, With<CombineOptionText>>,
+        mut buttons: Query<(&mut Style, &mut Visibility), With<EventButton<CycleCombineOption>>>,
+    ) {
+        if !options.is_changed() {
+            return;
+        }
+        let label = options.chosen().map(|option| {
+            format!(
+                "{} {}/{}",
+                Tower::Special(option.recipe.typ),
+                options.chosen + 1,
+                options.options.len()
+            )
+        });
+        for mut text in &mut texts {
+            text.sections[0].value = label.clone().unwrap_or_default();
+        }
+        for (mut style, mut visibility) in &mut buttons {
+            (style.display, *visibility) = if label.is_some() {
+                (Display::Flex, Visibility::Inherited)
+            } else {
+                (Display::None, Visibility::Hidden)
+            };
+        }
+    }
+}
+
+/// Where the game is at: level, phase, what is left of the wave and what comes next
+#[derive(Component)]
+struct WaveHud;
+
+impl WaveHud {
+    fn update(
+        ass: Res<AssetServer>,
+        level: Res<CurrentLevel>,
+        phase: Res<State<Phase>>,
+        builds: Res<Builds>,
+        spawners: Query<&CreepSpawner>,
+        creeps: Query<(), With<Creep>>,
+        mut text: Query<&mut Text, With<WaveHud>>,
+    ) {
+        let Ok(mut text) = text.get_single_mut() else {
+            return;
+        };
+        let to_spawn = spawners.iter().map(|spawner| spawner.amount).sum::<u32>();
+        let alive = creeps.iter().count();
+        // The level only moves on once the wave is over
+        let next_level = if phase.0 == Phase::Spawn {
+            **level + 1
+        } else {
+            **level
+        };
+        let next_type = match CreepType::from_level(next_level) {
+            CreepType::Ground => "ground",
+            CreepType::Flying => "flying",
+        };
+        let next_hitpoints = HitPoints::from_level(next_level).max();
+        let lines = [
+            format!("Level {} - {:?}", **level, phase.0),
+            format!("Builds left: {}", **builds),
+            format!("Creeps: {to_spawn} to come, {alive} alive"),
+            format!("Next wave: {next_type}, {next_hitpoints} HP"),
+        ];
+        let value = lines.join("\n");
+        if text.sections.first().map(|section| &section.value) == Some(&value) {
+            return;
+        }
+        *text = Text::from_section(
+            value,
+            TextStyle {
+                font: ass.load("Mukta-Regular.ttf"),
+                font_size: 24.,
+                color: Color::ANTIQUE_WHITE,
+            },
+        );
+    }
+}
+
 #[derive(Component)]
 struct SelectedText;
 
@@ -276,11 +676,17 @@ impl SelectedText {
         ass: Res<AssetServer>,
         selected: Option<Res<SelectedTower>>,
         mut text: Query<&mut Text, With<SelectedText>>,
-        tower_stats: Query<(&Name, Option<&LaserAttack>, Option<&Cooldown>)>,
+        tower_stats: Query<(
+            &Name,
+            Option<&LaserAttack>,
+            Option<&Cooldown>,
+            Option<&TowerStats>,
+        )>,
+        changed_stats: Query<(), Changed<TowerStats>>,
     ) {
         if let Some(selected) = selected {
-            if selected.is_changed() {
-                if let Ok((name, attack, cooldown)) = tower_stats.get(selected.tower) {
+            if selected.is_changed() || changed_stats.contains(selected.tower) {
+                if let Ok((name, attack, cooldown, stats)) = tower_stats.get(selected.tower) {
                     let mut text = text.single_mut();
                     let mut style = TextStyle {
                         font: ass.load("Mukta-Regular.ttf"),
@@ -315,7 +721,19 @@ impl SelectedText {
                     }
                     if let Some(Cooldown(timer)) = cooldown {
                         text_section.sections.push(TextSection::new(
-                            format!("Attack speed: {}", timer.duration().as_secs_f32()),
+                            format!("Attack speed: {}\n", timer.duration().as_secs_f32()),
+                            style.clone(),
+                        ));
+                    }
+                    if let Some(TowerStats { total, .. }) = stats {
+                        text_section.sections.push(TextSection::new(
+                            format!(
+                                "Dealt: {} ({} kills)\nOverkill: {}\nUptime: {:.0}%",
+                                total.damage,
+                                total.kills,
+                                total.overkill,
+                                total.uptime() * 100.
+                            ),
                             style,
                         ));
                     }
@@ -329,6 +747,534 @@ impl SelectedText {
     }
 }
 
+/// Scales the UI along with the window height so the sidebar keeps its proportions at any size
+/// or DPI
+fn scale_ui(windows: Query<&Window, Changed<Window>>, mut ui_scale: ResMut<UiScale>) {
+    if let Ok(window) = windows.get_single() {
+        let scale = f64::from(window.height() / WINDOW_HEIGHT);
+        if scale > 0. && (ui_scale.scale - scale).abs() > f64::EPSILON {
+            ui_scale.scale = scale;
+        }
+    }
+}
+
+/// Table of how every tower did in the wave that just ended, shown until the next wave starts
+#[derive(Component)]
+struct WaveSummary;
+
+impl WaveSummary {
+    const COLUMN_WIDTHS: [f32; 5] = [180., 80., 60., 80., 70.];
+
+    fn spawn(
+        mut commands: Commands,
+        ass: Res<AssetServer>,
+        level: Res<CurrentLevel>,
+        towers: Query<(&Name, &TowerStats), With<LaserAttack>>,
+    ) {
+        let style = TextStyle {
+            font: ass.load("Mukta-Regular.ttf"),
+            font_size: 20.,
+            color: Color::ANTIQUE_WHITE,
+        };
+        let mut towers = towers.iter().collect::<Vec<_>>();
+        towers.sort_by_key(|(_, stats)| std::cmp::Reverse(stats.wave.damage));
+        let header = ["Tower", "Damage", "Kills", "Overkill", "Uptime"].map(String::from);
+        let rows = std::iter::once(header).chain(towers.into_iter().map(|(name, stats)| {
+            [
+                name.to_string(),
+                stats.wave.damage.to_string(),
+                stats.wave.kills.to_string(),
+                stats.wave.overkill.to_string(),
+                format!("{:.0}%", stats.wave.uptime() * 100.),
+            ]
+        }));
+
+        let table = commands
+            .spawn((
+                NodeBundle {
+                    background_color: Color::rgba(0., 0., 0., 0.7).into(),
+                    style: Style {
+                        position_type: PositionType::Absolute,
+                        position: UiRect {
+                            left: Val::Px(10.),
+                            top: Val::Px(10.),
+                            ..default()
+                        },
+                        flex_direction: FlexDirection::Column,
+                        padding: UiRect::all(Val::Px(5.)),
+                        ..default()
+                    },
+                    ..default()
+                },
+                WaveSummary,
+            ))
+            .id();
+        let title = commands
+            .spawn(TextBundle::from_section(
+                // Runs after the level has moved on to the next wave
+                format!("Wave {} summary", **level - 1),
+                style.clone(),
+            ))
+            .id();
+        commands.entity(table).add_child(title);
+
+        for cells in rows {
+            let row = commands
+                .spawn(NodeBundle {
+                    style: Style {
+                        flex_direction: FlexDirection::Row,
+                        ..default()
+                    },
+                    ..default()
+                })
+                .id();
+            for (cell, width) in cells.into_iter().zip(Self::COLUMN_WIDTHS) {
+                let cell = commands
+                    .spawn(TextBundle {
+                        text: Text::from_section(cell, style.clone()),
+                        style: Style {
+                            size: Size::width(Val::Px(width)),
+                            ..default()
+                        },
+                        ..default()
+                    })
+                    .id();
+                commands.entity(row).add_child(cell);
+            }
+            commands.entity(table).add_child(row);
+        }
+    }
+
+    fn despawn(mut commands: Commands, summaries: Query<Entity, With<WaveSummary>>) {
+        for summary in &summaries {
+            commands.entity(summary).despawn_recursive();
+        }
+    }
+}
+
+/// Something worth telling the player, shown as a toast and kept in the [`MessageLog`]
+pub struct Notification {
+    pub text: String,
+    pub color: Color,
+}
+
+impl Notification {
+    pub fn info(text: impl Into<String>) -> Self {
+        Self {
+            text: text.into(),
+            color: Color::ANTIQUE_WHITE,
+        }
+    }
+
+    pub fn warning(text: impl Into<String>) -> Self {
+        Self {
+            text: text.into(),
+            color: Color::ORANGE,
+        }
+    }
+
+    pub fn error(text: impl Into<String>) -> Self {
+        Self {
+            text: text.into(),
+            color: Color::ORANGE_RED,
+        }
+    }
+
+    fn from_errors(mut errors: EventReader<GameError>, mut notifications: EventWriter<Self>) {
+        notifications.send_batch(errors.iter().map(|err| Self::error(err.to_string())));
+    }
+
+    fn from_save_errors(mut errors: EventReader<SaveError>, mut notifications: EventWriter<Self>) {
+        notifications.send_batch(errors.iter().map(|err| Self::error(err.to_string())));
+    }
+
+    fn level_started(level: Res<CurrentLevel>, mut notifications: EventWriter<Self>) {
+        notifications.send(Self::info(format!("Level {} started", **level)));
+    }
+
+    /// One notification per frame no matter how many creeps got through at once
+    fn leaks(mut leaked: EventReader<Leaked>, mut notifications: EventWriter<Self>) {
+        match leaked.len() {
+            0 => {}
+            1 => notifications.send(Self::warning("A creep leaked")),
+            count => notifications.send(Self::warning(format!("{count} creeps leaked"))),
+        }
+        leaked.clear();
+    }
+
+    fn special_towers(
+        mut combined: EventReader<SpecialTowerCombined>,
+        mut notifications: EventWriter<Self>,
+    ) {
+        notifications.send_batch(
+            combined
+                .iter()
+                .map(|combined| Self::info(format!("{} created", Tower::Special(combined.typ)))),
+        );
+    }
+
+    fn text_bundle(&self, ass: &AssetServer, font_size: f32) -> TextBundle {
+        TextBundle::from_section(
+            self.text.clone(),
+            TextStyle {
+                font: ass.load("Mukta-Regular.ttf"),
+                font_size,
+                color: self.color,
+            },
+        )
+    }
+}
+
+/// A [`Notification`] shown over the map for a few seconds
+#[derive(Component)]
+struct Toast;
+
+/// Column the toasts are stacked in, newest at the bottom
+#[derive(Component)]
+struct ToastStack;
+
+impl Toast {
+    const LIFETIME: f32 = 4.;
+    /// Older toasts are dropped early so they never cover too much of the map
+    const MAX_SHOWN: usize = 4;
+
+    fn spawn_stack(mut commands: Commands) {
+        commands.spawn((
+            NodeBundle {
+                style: Style {
+                    position_type: PositionType::Absolute,
+                    position: UiRect {
+                        left: Val::Px(20.),
+                        bottom: Val::Px(20.),
+                        ..default()
+                    },
+                    flex_direction: FlexDirection::Column,
+                    ..default()
+                },
+                // Above the menus, a failed load from the main menu has to show
+                z_index: ZIndex::Global(20),
+                ..default()
+            },
+            ToastStack,
+        ));
+    }
+
+    fn spawn(
+        mut commands: Commands,
+        ass: Res<AssetServer>,
+        mut notifications: EventReader<Notification>,
+        stack: Query<(Entity, Option<&Children>), With<ToastStack>>,
+    ) {
+        let Ok((stack, shown)) = stack.get_single() else {
+            return;
+        };
+        let mut shown = shown.map(|shown| shown.to_vec()).unwrap_or_default();
+        for notification in notifications.iter() {
+            let toast = commands
+                .spawn((
+                    notification.text_bundle(&ass, 26.),
+                    Fadeout(Timer::from_seconds(Self::LIFETIME, TimerMode::Once)),
+                    RawTime,
+                    Toast,
+                ))
+                .id();
+            commands.entity(stack).add_child(toast);
+            shown.push(toast);
+        }
+        let excess = shown.len().saturating_sub(Self::MAX_SHOWN);
+        for toast in shown.drain(..excess) {
+            commands.entity(toast).despawn_recursive();
+        }
+    }
+
+    /// Fades out over the last quarter of the toast's life
+    fn fade(mut toasts: Query<(&mut Text, &Fadeout), With<Toast>>) {
+        for (mut text, fadeout) in &mut toasts {
+            for section in &mut text.sections {
+                section
+                    .style
+                    .color
+                    .set_a((fadeout.percent_left() * 4.).min(1.));
+            }
+        }
+    }
+}
+
+/// Every [`Notification`] of the game so far, newest on top, scrolled with the mouse wheel while
+/// hovered
+#[derive(Component, Default)]
+struct MessageLog {
+    /// Pixels scrolled down from the newest message
+    scrolled: f32,
+}
+
+impl MessageLog {
+    const HEIGHT: f32 = 150.;
+    const WIDTH: f32 = 240.;
+    /// Oldest messages are dropped past this
+    const MAX_MESSAGES: usize = 100;
+    const FONT_SIZE: f32 = 18.;
+
+    fn push(
+        mut commands: Commands,
+        ass: Res<AssetServer>,
+        mut notifications: EventReader<Notification>,
+        log: Query<(Entity, Option<&Children>), With<MessageLog>>,
+    ) {
+        let Ok((log, messages)) = log.get_single() else {
+            return;
+        };
+        // Only the newest fit when more arrive at once than the log holds
+        let notifications = notifications.iter().collect::<Vec<_>>();
+        let new = &notifications[notifications.len().saturating_sub(Self::MAX_MESSAGES)..];
+        for notification in new {
+            let message = commands
+                .spawn(
+                    notification
+                        .text_bundle(&ass, Self::FONT_SIZE)
+                        .with_style(Style {
+                            // Wrap long messages instead of widening the sidebar
+                            max_size: Size::width(Val::Px(Self::WIDTH)),
+                            ..default()
+                        }),
+                )
+                .id();
+            commands.entity(log).insert_children(0, &[message]);
+        }
+        if let Some(messages) = messages {
+            // The newly inserted messages aren't among the children yet, so the oldest are at
+            // the end of the current ones
+            let excess = (messages.len() + new.len()).saturating_sub(Self::MAX_MESSAGES);
+            for message in &messages[messages.len() - excess..] {
+                commands.entity(*message).despawn_recursive();
+            }
+        }
+    }
+
+    fn scroll(
+        mut wheel: EventReader<MouseWheel>,
+        mut logs: Query<(&mut MessageLog, &mut Style, &Node, &Parent)>,
+        panels: Query<(&Node, &Interaction)>,
+    ) {
+        let scrolled = wheel
+            .iter()
+            .map(|event| match event.unit {
+                MouseScrollUnit::Line => event.y * Self::FONT_SIZE,
+                MouseScrollUnit::Pixel => event.y,
+            })
+            .sum::<f32>();
+        if scrolled == 0. {
+            return;
+        }
+        for (mut log, mut style, list, parent) in &mut logs {
+            let Ok((panel, interaction)) = panels.get(parent.get()) else {
+                continue;
+            };
+            if *interaction == Interaction::None {
+                continue;
+            }
+            let max_scroll = (list.size().y - panel.size().y).max(0.);
+            log.scrolled = (log.scrolled - scrolled).clamp(0., max_scroll);
+            style.position.top = Val::Px(-log.scrolled);
+        }
+    }
+}
+
+/// Every special tower recipe, with the ingredients the player already has and the recipes that
+/// can be combined right now picked out
+#[derive(Component)]
+struct RecipeBook;
+
+impl RecipeBook {
+    const FONT_SIZE: f32 = 20.;
+
+    fn spawn(mut commands: Commands) {
+        commands.spawn((
+            TextBundle {
+                style: Style {
+                    position_type: PositionType::Absolute,
+                    position: UiRect {
+                        left: Val::Percent(30.),
+                        top: Val::Px(10.),
+                        ..default()
+                    },
+                    padding: UiRect::all(Val::Px(5.)),
+                    ..default()
+                },
+                background_color: Color::rgba(0., 0., 0., 0.7).into(),
+                visibility: Visibility::Hidden,
+                ..default()
+            },
+            RecipeBook,
+        ));
+    }
+
+    fn toggle(
+        keys: Res<Input<KeyCode>>,
+        keymap: Res<Keymap>,
+        mut books: Query<&mut Visibility, With<RecipeBook>>,
+    ) {
+        if !keys.just_pressed(keymap.recipes) {
+            return;
+        }
+        for mut visibility in &mut books {
+            *visibility = match *visibility {
+                Visibility::Hidden => Visibility::Inherited,
+                _ => Visibility::Hidden,
+            };
+        }
+    }
+
+    fn update(
+        ass: Res<AssetServer>,
+        recipes: Res<SpecialTowerRecipes>,
+        fulfillable: Res<FulfillableSpecialTowerRecipes>,
+        towers: Query<&Tower>,
+        changed_towers: Query<(), Changed<Tower>>,
+        mut removed_towers: RemovedComponents<Tower>,
+        mut books: Query<&mut Text, With<RecipeBook>>,
+    ) {
+        let towers_changed = !changed_towers.is_empty() || removed_towers.iter().count() > 0;
+        if !towers_changed && !fulfillable.is_changed() && !recipes.is_changed() {
+            return;
+        }
+        let style = |color| TextStyle {
+            font: ass.load("Mukta-Regular.ttf"),
+            font_size: Self::FONT_SIZE,
+            color,
+        };
+        let mut sections = vec![TextSection::new("Recipes", style(Color::ANTIQUE_WHITE))];
+        for recipe in recipes.iter() {
+            let ready = fulfillable.contains(recipe);
+            sections.push(TextSection::new(
+                format!(
+                    "\n{}{}\n",
+                    Tower::Special(recipe.typ),
+                    if ready { " - ready" } else { "" }
+                ),
+                style(if ready {
+                    Color::GOLD
+                } else {
+                    Color::ANTIQUE_WHITE
+                }),
+            ));
+            // Each tower only counts towards one ingredient so recipes asking for the same gem
+            // twice need two of them
+            let mut owned = towers.iter().copied().collect::<Vec<_>>();
+            for (index, ingredient) in recipe.ingredients.iter().enumerate() {
+                let has = owned
+                    .iter()
+                    .position(|tower| tower == ingredient)
+                    .map(|position| owned.swap_remove(position))
+                    .is_some();
+                sections.push(TextSection::new(
+                    format!("{}{ingredient}", if index == 0 { "  " } else { ", " }),
+                    style(if has { Color::LIME_GREEN } else { Color::GRAY }),
+                ));
+            }
+        }
+        for mut text in &mut books {
+            text.sections.clone_from(&sections);
+        }
+    }
+}
+
+/// Amount of damage a creep just took, rises from the creep and fades away
+#[derive(Component)]
+struct DamageNumber;
+
+impl DamageNumber {
+    const LIFETIME: f32 = 0.8;
+    /// Pixels per second
+    const RISE_SPEED: f32 = 40.;
+
+    fn spawn(
+        mut commands: Commands,
+        ass: Res<AssetServer>,
+        config: Res<Config>,
+        mut dealt: EventReader<DamageDealt>,
+        creeps: Query<&GlobalTransform>,
+    ) {
+        for dealt in dealt.iter() {
+            if !config.damage_numbers || dealt.amount == 0 {
+                continue;
+            }
+            let (color, font_size) = match dealt.kind {
+                DamageKind::Attack => (Color::ANTIQUE_WHITE, 20.),
+                DamageKind::Crit => (Color::ORANGE, 30.),
+                DamageKind::Splash => (Color::ALICE_BLUE, 16.),
+                DamageKind::Poison => (Color::LIME_GREEN, 16.),
+            };
+            commands.spawn((
+                TextBundle {
+                    text: Text::from_section(
+                        dealt.amount.to_string(),
+                        TextStyle {
+                            font: ass.load("Mukta-Regular.ttf"),
+                            font_size,
+                            color,
+                        },
+                    ),
+                    style: Style {
+                        position_type: PositionType::Absolute,
+                        // Tracking needs a fixed size to center the text on the creep
+                        size: Size::new(Val::Px(60.), Val::Px(font_size)),
+                        ..default()
+                    },
+                    // Hidden until it has been moved onto the creep
+                    visibility: Visibility::Hidden,
+                    ..default()
+                },
+                TrackWorldObjectToScreenPosition {
+                    target: dealt.target,
+                    // Spread out numbers that land in the same frame, purely cosmetic so this
+                    // stays off the game RNG
+                    offset: Vec2::new(fastrand::f32() * 30. - 15., -30.),
+                    // A killing blow despawns the creep this frame, before tracking first sees
+                    // it, so the number starts out where the creep was hit
+                    last_position: creeps
+                        .get(dealt.target)
+                        .ok()
+                        .map(GlobalTransform::translation),
+                },
+                Fadeout(Timer::from_seconds(Self::LIFETIME, TimerMode::Once)),
+                DamageNumber,
+            ));
+        }
+    }
+
+    fn float(
+        time: Res<Time>,
+        mut numbers: Query<
+            (
+                &mut TrackWorldObjectToScreenPosition,
+                &Style,
+                &mut Text,
+                &mut Visibility,
+                &Fadeout,
+            ),
+            With<DamageNumber>,
+        >,
+    ) {
+        for (mut tracking, style, mut text, mut visibility, fadeout) in &mut numbers {
+            // Only shown once tracking has placed it on screen
+            if let Val::Px(_) = style.position.left {
+                *visibility = Visibility::Inherited;
+            }
+            tracking.offset.y -= Self::RISE_SPEED * time.delta_seconds();
+            for section in &mut text.sections {
+                section.style.color.set_a(fadeout.percent_left());
+            }
+        }
+    }
+
+    fn toggle(keys: Res<Input<KeyCode>>, keymap: Res<Keymap>, mut config: ResMut<Config>) {
+        if keys.just_pressed(keymap.damage_numbers) {
+            config.damage_numbers = !config.damage_numbers;
+        }
+    }
+}
+
 #[derive(Bundle)]
 struct EventButtonBundle<T: Default + Send + Sync + 'static> {
     #[bundle]
@@ -345,13 +1291,13 @@ impl<T: Default> EventButton<T> {
     }
 }
 
-fn event_buttons<T: Default + Send + Sync + 'static>(
-    mut events: EventWriter<T>,
+fn event_buttons<T: Default + Into<GameCommand> + Send + Sync + 'static>(
+    mut events: EventWriter<GameCommand>,
     buttons: Query<&Interaction, (Changed<Interaction>, With<EventButton<T>>)>,
 ) {
     for interaction in &buttons {
         if let Interaction::Clicked = interaction {
-            events.send(T::default());
+            events.send(T::default().into());
         }
     }
 }
@@ -378,6 +1324,28 @@ fn show_pickable_button(
     }
 }
 
+fn show_merge_button(
+    selected: Option<Res<SelectedTower>>,
+    mut buttons: Query<(&mut Style, &mut Visibility), With<EventButton<MergeSelectedTower>>>,
+) {
+    if let Some(selected) = selected {
+        if selected.is_changed() {
+            for (mut style, mut visibility) in &mut buttons {
+                (style.display, *visibility) = if selected.mergeable {
+                    (Display::Flex, Visibility::Inherited)
+                } else {
+                    (Display::None, Visibility::Hidden)
+                }
+            }
+        }
+    } else {
+        for (mut style, mut visibility) in &mut buttons {
+            style.display = Display::None;
+            *visibility = Visibility::Hidden;
+        }
+    }
+}
+
 fn show_refine_and_pick_button(
     selected: Option<Res<SelectedTower>>,
     mut buttons: Query<
@@ -0,0 +1,196 @@
+use std::{
+    fs,
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use bevy::{ecs::system::SystemState, prelude::*, time::TimeUpdateStrategy, utils::HashMap};
+use bevy_prototype_debug_lines::DebugLines;
+use seldom_map_nav::prelude::*;
+use serde::Deserialize;
+
+use crate::{
+    common::GameRng,
+    creeps::{DamageDealt, DamageKind, DamageSet, Dead, Leaked},
+    towers::{BuildGrid, GemChances, Tower},
+    CurrentLevel, GameplayPlugin, Phase,
+};
+
+/// Simulated time per update, the same as a game running at 60 fps
+pub const TIMESTEP: Duration = Duration::from_nanos(1_000_000_000 / 60);
+/// A wave that runs longer than this most likely has a creep stuck behind a blocked path
+const MAX_WAVE_TIME: Duration = Duration::from_mins(10);
+
+/// Tower layout and number of waves to simulate, read from a RON file
+#[derive(Deserialize)]
+pub struct Scenario {
+    #[serde(default)]
+    pub seed: Option<u64>,
+    pub waves: u32,
+    pub towers: Vec<ScriptedTower>,
+}
+
+#[derive(Deserialize)]
+pub struct ScriptedTower {
+    pub tower: Tower,
+    pub tile: UVec2,
+}
+
+#[derive(Default, Resource)]
+pub struct WaveReport {
+    pub kills: HashMap<DamageKind, u32>,
+    pub leaks: u32,
+    pub damage: u64,
+}
+
+impl WaveReport {
+    fn track(
+        mut report: ResMut<WaveReport>,
+        mut dealt: EventReader<DamageDealt>,
+        mut deaths: EventReader<Dead>,
+        mut leaked: EventReader<Leaked>,
+    ) {
+        for dealt in dealt.iter() {
+            report.damage += u64::from(dealt.amount);
+        }
+        for Dead { kind, .. } in deaths.iter() {
+            *report.kills.entry(*kind).or_default() += 1;
+        }
+        report.leaks += leaked.iter().count() as u32;
+    }
+
+    fn row(&self, label: &str) -> String {
+        let killed_by = [
+            DamageKind::Attack,
+            DamageKind::Crit,
+            DamageKind::Splash,
+            DamageKind::Poison,
+        ]
+        .into_iter()
+        .filter_map(|kind| Some(format!("{kind} {}", self.kills.get(&kind)?)))
+        .collect::<Vec<_>>()
+        .join(", ");
+        format!(
+            "{label:>5} {:>6} {:>6} {:>8}  {killed_by}",
+            self.kills.values().sum::<u32>(),
+            self.leaks,
+            self.damage
+        )
+    }
+}
+
+/// Plays through a scenario without a window or renderer as fast as possible, printing how every
+/// wave went
+pub fn run(path: &Path, seed: Option<u64>, chances: GemChances) {
+    let scenario: Scenario = match fs::read_to_string(path) {
+        Ok(text) => match ron::from_str(&text) {
+            Ok(scenario) => scenario,
+            Err(err) => {
+                eprintln!("Could not read scenario {}: {err}", path.display());
+                return;
+            }
+        },
+        Err(err) => {
+            eprintln!("Could not access scenario {}: {err}", path.display());
+            return;
+        }
+    };
+    let seed = seed.or(scenario.seed).unwrap_or_else(|| fastrand::u64(..));
+    println!("Game seed: {seed}");
+
+    let mut app = app(seed);
+    app.insert_resource(chances)
+        .init_resource::<WaveReport>()
+        .add_system(WaveReport::track.in_set(DamageSet::Resolve));
+
+    // Run startup so the map exists before placing towers on it
+    app.update();
+    place_towers(&mut app.world, scenario.towers);
+
+    println!(
+        "{:>5} {:>6} {:>6} {:>8}  Killed by",
+        "Wave", "Kills", "Leaks", "Damage"
+    );
+    let mut total = WaveReport::default();
+    for _ in 0..scenario.waves {
+        let wave = **app.world.resource::<CurrentLevel>();
+        app.world
+            .resource_mut::<NextState<Phase>>()
+            .set(Phase::Spawn);
+        let mut elapsed = Duration::ZERO;
+        let finished = loop {
+            elapsed += TIMESTEP;
+            step(&mut app);
+            if app.world.resource::<State<Phase>>().0 == Phase::Build {
+                break true;
+            }
+            if elapsed >= MAX_WAVE_TIME {
+                break false;
+            }
+        };
+
+        let report = std::mem::take(&mut *app.world.resource_mut::<WaveReport>());
+        println!("{}", report.row(&wave.to_string()));
+        for (kind, kills) in report.kills {
+            *total.kills.entry(kind).or_default() += kills;
+        }
+        total.leaks += report.leaks;
+        total.damage += report.damage;
+        if !finished {
+            eprintln!(
+                "Wave {wave} did not end within {} seconds, stopping",
+                MAX_WAVE_TIME.as_secs()
+            );
+            break;
+        }
+    }
+    println!("{}", total.row("Total"));
+}
+
+/// The gameplay without a window, renderer or input, time only passes when calling [`step`]
+pub fn app(seed: u64) -> App {
+    let mut app = App::new();
+    app.add_plugins(MinimalPlugins)
+        .add_plugin(TransformPlugin)
+        .add_plugin(HierarchyPlugin)
+        .add_plugin(AssetPlugin::default())
+        .add_asset::<Mesh>()
+        .add_asset::<StandardMaterial>()
+        .init_resource::<DebugLines>()
+        .insert_resource(GameRng::new(seed))
+        .insert_resource(TimeUpdateStrategy::ManualInstant(Instant::now()))
+        .add_plugin(GameplayPlugin);
+    app
+}
+
+/// Advances the game by one [`TIMESTEP`]
+pub fn step(app: &mut App) {
+    if let TimeUpdateStrategy::ManualInstant(now) =
+        app.world.resource_mut::<TimeUpdateStrategy>().as_mut()
+    {
+        *now += TIMESTEP;
+    }
+    app.update();
+}
+
+fn place_towers(world: &mut World, towers: Vec<ScriptedTower>) {
+    let mut state: SystemState<(
+        Commands,
+        ResMut<Assets<Mesh>>,
+        ResMut<Assets<StandardMaterial>>,
+        ResMut<BuildGrid>,
+        Query<Entity, With<Navmeshes>>,
+    )> = SystemState::new(world);
+    let (mut commands, mut meshes, mut mats, mut build_grid, navmeshes) = state.get_mut(world);
+    for ScriptedTower { tower, tile } in towers {
+        if !build_grid.occupy(tile) {
+            eprintln!("Cannot place {tower} at {tile}, the tile is taken or off the map");
+            continue;
+        }
+        tower.spawn(&mut commands, &mut meshes, &mut mats, tile.as_vec2());
+    }
+    commands
+        .entity(navmeshes.single())
+        .insert(build_grid.navmeshes());
+    state.apply(world);
+}
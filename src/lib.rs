@@ -0,0 +1,220 @@
+#![deny(clippy::all)]
+#![warn(clippy::pedantic, clippy::cargo)]
+#![allow(
+    clippy::module_name_repetitions,
+    clippy::cargo_common_metadata,
+    clippy::type_complexity,
+    clippy::too_many_arguments,
+    clippy::needless_pass_by_value,
+    clippy::multiple_crate_versions,
+    clippy::cast_possible_truncation,
+    clippy::cast_precision_loss,
+    clippy::too_many_lines,
+    clippy::similar_names,
+    clippy::must_use_candidate,
+    clippy::enum_glob_use,
+    clippy::missing_errors_doc,
+    clippy::missing_panics_doc,
+    clippy::return_self_not_must_use
+)]
+#![feature(is_some_and)]
+
+//! Gem TD as a set of plugins, [`GemTdPlugin`] is the whole game while [`GameplayPlugin`] is
+//! just the rules so simulators, tests and mods can put the game together differently
+
+use bevy::prelude::{shape::Plane, *};
+use bevy_rapier3d::prelude::*;
+use camera::{CameraController, CameraControlsPlugin};
+use common::{Builds, CreepPos};
+use controls::{BuildTower, ControlsPlugin, GameCommand, SelectTower};
+use creeps::{CreepSpawner, CreepsPlugin};
+use gui::GameGuiPlugin;
+use menu::MenuPlugin;
+use replay::Replay;
+use save::{GameLoaded, LoadGame, SaveError, SaveGame};
+use seldom_map_nav::prelude::*;
+use serde::{Deserialize, Serialize};
+use speed::GameSpeedPlugin;
+use towers::{BuildGrid, NavmeshTiles, TowersPlugin};
+
+pub mod camera;
+pub mod common;
+pub mod config;
+pub mod controls;
+pub mod creeps;
+#[cfg(feature = "dev-tools")]
+pub mod debug;
+pub mod gui;
+pub mod headless;
+pub mod menu;
+pub mod progress_bar;
+pub mod replay;
+pub mod save;
+pub mod speed;
+pub mod stats;
+pub mod tower_abilities;
+pub mod towers;
+
+pub const CLEAR: Color = Color::BLACK;
+/// Height the UI is laid out for, it is scaled to fit the actual window
+pub const WINDOW_HEIGHT: f32 = 800.0;
+pub const RESOLUTION: f32 = 16.0 / 9.0;
+pub const CAMERA_OFFSET: [f32; 3] = [0.0, 12.0, 10.0];
+pub const CREEP_CLEARANCE: f32 = 0.25;
+
+/// The whole game with input, camera, menus and GUI on top of [`GameplayPlugin`], expects the
+/// default plugins, a [`common::GameRng`], [`config::Config`], [`config::Keymap`], [`Replay`] and
+/// the [`towers::GemChances`] from [`config::Config::gem_chances`] after
+/// [`towers::GemChances::validated`], the plugin otherwise rolls gems with the default odds
+pub struct GemTdPlugin;
+
+impl Plugin for GemTdPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugin(GameplayPlugin)
+            .add_plugin(ControlsPlugin)
+            .add_plugin(GameGuiPlugin)
+            .add_plugin(CameraControlsPlugin)
+            .add_plugin(GameSpeedPlugin)
+            .add_state::<AppState>()
+            .add_plugin(MenuPlugin)
+            .add_startup_system(setup_scene)
+            .add_systems((
+                Replay::record_commands.in_base_set(CoreSet::PreUpdate),
+                Replay::play_commands
+                    .in_base_set(CoreSet::PreUpdate)
+                    .before(GameCommand::dispatch),
+                replay::fixed_clock.in_base_set(CoreSet::Last),
+            ));
+    }
+}
+
+/// Everything that makes up the rules of the game, without any windowing, rendering or input so
+/// it can also run headless
+pub struct GameplayPlugin;
+
+impl Plugin for GameplayPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugin(MapNavPlugin::<CreepPos>::default())
+            .add_state::<Phase>()
+            .add_event::<GameCommand>()
+            .add_event::<BuildTower>()
+            .add_event::<SelectTower>()
+            .add_event::<SaveGame>()
+            .add_event::<LoadGame>()
+            .add_event::<GameLoaded>()
+            .add_event::<SaveError>()
+            .init_resource::<Builds>()
+            .init_resource::<CurrentLevel>()
+            .add_plugin(CreepsPlugin)
+            .add_plugin(TowersPlugin)
+            .add_startup_system(setup_map)
+            .add_systems((
+                Builds::reset_system.in_schedule(OnEnter(Phase::Build)),
+                check_state_change,
+                next_level.in_schedule(OnExit(Phase::Spawn)),
+                SaveGame::save,
+                LoadGame::load,
+                GameCommand::dispatch.in_base_set(CoreSet::PreUpdate),
+                BuildTower::build.in_set(OnUpdate(Phase::Build)),
+                SelectTower::select,
+            ));
+    }
+}
+
+pub const MAP_WIDTH: u32 = 4 * 4; // Originally 30 * 4
+pub const MAP_HEIGHT: u32 = 4 * 4; // Originally 22 * 4
+
+fn setup_scene(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut mats: ResMut<Assets<StandardMaterial>>,
+) {
+    let controller = CameraController::default();
+    commands.spawn((
+        Camera3dBundle {
+            transform: controller.transform(),
+            ..default()
+        },
+        controller,
+    ));
+
+    commands.spawn((
+        PbrBundle {
+            mesh: meshes.add(
+                Plane {
+                    size: MAP_WIDTH as f32,
+                    ..default()
+                }
+                .into(),
+            ),
+            material: mats.add(Color::DARK_GREEN.into()),
+            transform: Transform::from_xyz(MAP_WIDTH as f32 / 2., 0., MAP_HEIGHT as f32 / 2.),
+            ..default()
+        },
+        Collider::cuboid(MAP_WIDTH as f32 / 2., 0.01, MAP_HEIGHT as f32 / 2.),
+    ));
+}
+
+fn setup_map(mut commands: Commands, mut build_grid: ResMut<BuildGrid>) {
+    let tilemap = [Navability::Navable; ((MAP_WIDTH * MAP_HEIGHT) as usize)];
+    let navability = |pos: UVec2| tilemap[(pos.y * MAP_WIDTH + pos.x) as usize];
+    commands.spawn((
+        Navmeshes::generate(
+            [MAP_WIDTH, MAP_HEIGHT].into(),
+            Vec2::new(1., 1.),
+            navability,
+            [CREEP_CLEARANCE],
+        )
+        .unwrap(),
+        NavmeshTiles::default(),
+        Name::new("Navmesh"),
+    ));
+
+    commands.spawn((CreepSpawner::default(),));
+
+    build_grid.insert(UVec2::new(0, 15));
+    build_grid.insert(UVec2::new(0, 14));
+    build_grid.insert(UVec2::new(1, 14));
+    build_grid.insert(UVec2::new(1, 15));
+    build_grid.insert(UVec2::new(15, 0));
+    build_grid.insert(UVec2::new(14, 0));
+    build_grid.insert(UVec2::new(14, 1));
+    build_grid.insert(UVec2::new(15, 1));
+}
+
+/// Which screen the player is on, the game only advances while [`AppState::InGame`]
+#[derive(Clone, Copy, Debug, Default, Eq, Hash, PartialEq, States)]
+pub enum AppState {
+    #[default]
+    MainMenu,
+    Settings,
+    InGame,
+    Paused,
+}
+
+#[derive(Clone, Debug, Default, Eq, Hash, PartialEq, States, Serialize, Deserialize)]
+pub enum Phase {
+    #[default]
+    Build,
+    Pick,
+    Spawn,
+}
+
+fn check_state_change(state: Res<State<Phase>>) {
+    if state.is_changed() {
+        // println!("State changed to {state:?}");
+    }
+}
+
+#[derive(Resource, Clone, Copy, Deref, DerefMut)]
+pub struct CurrentLevel(u32);
+
+impl Default for CurrentLevel {
+    fn default() -> Self {
+        Self(1)
+    }
+}
+
+pub fn next_level(mut level: ResMut<CurrentLevel>) {
+    **level += 1;
+}
@@ -14,224 +14,97 @@
     clippy::must_use_candidate,
     clippy::enum_glob_use
 )]
-#![feature(is_some_and)]
 
-use bevy::{
-    prelude::{shape::Plane, *},
-    window::WindowResolution,
-};
-use bevy_debug_grid::DebugGridPlugin;
-use bevy_inspector_egui::quick::{ResourceInspectorPlugin, WorldInspectorPlugin};
+use std::time::Instant;
+
+use bevy::{prelude::*, time::TimeUpdateStrategy, window::WindowResolution};
 use bevy_prototype_debug_lines::DebugLinesPlugin;
 use bevy_rapier3d::prelude::*;
-use common::{
-    update_creep_position, Builds, CreepPos, Fadeout, MovingTo, TrackWorldObjectToScreenPosition,
-};
-use controls::{
-    build_on_click, cursor_over_gui, remove_highlight, show_highlight, update_under_cursor,
-    CursorOverGui, SelectedTower, UnderCursor,
+use gem_td::{
+    common::GameRng,
+    config::{Args, Config},
+    headless,
+    replay::Replay,
+    AppState, GemTdPlugin, CLEAR, RESOLUTION, WINDOW_HEIGHT,
 };
-use creeps::{CreepSpawner, Dead, Hit, HitPoints, Slow};
-use gui::GameGuiPlugin;
-use seldom_map_nav::prelude::*;
-use tower_abilities::TowerAbilitiesPlugin;
-use towers::{
-    rebuild_navmesh, uncover_dirt, BuildGrid, CombineSelectedTower, FulfillableSpecialTowerRecipes,
-    LaserAttack, PickSelectedTower, RandomLevel, RefineAndPickSelectedTower, RemoveSelectedTower,
-    SpecialTowerRecipes, UpdateFulfillableSpecialTowerRecipes,
-};
-
-mod common;
-mod controls;
-mod creeps;
-mod gui;
-mod progress_bar;
-mod tower_abilities;
-mod towers;
-
-pub const CLEAR: Color = Color::BLACK;
-pub const WINDOW_HEIGHT: f32 = 800.0;
-pub const RESOLUTION: f32 = 16.0 / 9.0;
-pub const CAMERA_OFFSET: [f32; 3] = [0.0, 12.0, 10.0];
-pub const CREEP_CLEARANCE: f32 = 0.25;
 
 fn main() {
-    App::new()
-        .insert_resource(AmbientLight {
-            brightness: 1.0,
-            color: Color::WHITE,
+    let config = Config::load();
+    let args = Args::parse();
+    if let Some(path) = &args.headless {
+        headless::run(
+            path,
+            args.seed.or(config.seed),
+            config.gem_chances.clone().validated(),
+        );
+        return;
+    }
+    let mut seed = args
+        .seed
+        .or(config.seed)
+        .unwrap_or_else(|| fastrand::u64(..));
+    let replay = if let Some(path) = &args.replay {
+        match Replay::playback(path) {
+            Ok((replay, recorded_seed)) => {
+                // The recorded commands only make sense with the randomness they were given in
+                seed = recorded_seed;
+                replay
+            }
+            Err(err) => {
+                eprintln!("{err}");
+                Replay::Off
+            }
+        }
+    } else if let Some(path) = &args.record {
+        Replay::record(path, seed).unwrap_or_else(|err| {
+            eprintln!("{err}");
+            Replay::Off
         })
-        .insert_resource(ClearColor(CLEAR))
-        // External plugins
-        .add_plugins(
-            DefaultPlugins
-                .set(WindowPlugin {
-                    primary_window: Some(Window {
-                        resolution: WindowResolution::new(
-                            WINDOW_HEIGHT * RESOLUTION,
-                            WINDOW_HEIGHT,
-                        )
-                        .with_scale_factor_override(1.),
-                        title: "GEM TD".to_string(),
-                        resizable: false,
-                        ..default()
-                    }),
-                    ..default()
-                })
-                .set(ImagePlugin::default_nearest()),
-        )
-        .add_plugin(DebugLinesPlugin::with_depth_test(true))
-        .add_plugin(MapNavPlugin::<CreepPos>::default())
-        .add_plugin(WorldInspectorPlugin::new())
-        .add_plugin(RapierPhysicsPlugin::<NoUserData>::default())
-        .add_plugin(DebugGridPlugin::with_floor_grid())
-        // Internal plugins
-        .add_state::<Phase>()
-        .add_event::<Hit>()
-        .add_event::<Dead>()
-        .add_event::<PickSelectedTower>()
-        .add_event::<RemoveSelectedTower>()
-        .add_event::<RefineAndPickSelectedTower>()
-        .add_event::<UpdateFulfillableSpecialTowerRecipes>()
-        .add_event::<CombineSelectedTower>()
-        .init_resource::<Builds>()
-        .init_resource::<CurrentLevel>()
-        .init_resource::<UnderCursor>()
-        .init_resource::<BuildGrid>()
-        .init_resource::<RandomLevel>()
-        .init_resource::<CursorOverGui>()
-        .init_resource::<SpecialTowerRecipes>()
-        .init_resource::<FulfillableSpecialTowerRecipes>()
-        .register_type::<FulfillableSpecialTowerRecipes>()
-        .add_plugin(ResourceInspectorPlugin::<FulfillableSpecialTowerRecipes>::default())
-        .add_plugin(TowerAbilitiesPlugin)
-        .add_plugin(GameGuiPlugin)
-        .add_startup_system(startup)
-        .add_systems((
-            update_under_cursor,
-            LaserAttack::attack,
-            Fadeout::fadeout,
-            Hit::consume,
-            TrackWorldObjectToScreenPosition::track,
-            MovingTo::move_to,
-            Dead::death,
-            CreepSpawner::spawn.in_set(OnUpdate(Phase::Spawn)),
-            HitPoints::spawn_health_bars,
-            HitPoints::update_health_bars,
-            Builds::reset_system.in_schedule(OnEnter(Phase::Build)),
-            show_highlight.in_set(OnUpdate(Phase::Build)),
-            build_on_click.in_set(OnUpdate(Phase::Build)),
-            update_creep_position,
-        ))
-        .add_systems((
-            CreepSpawner::reset_amount_system.in_schedule(OnEnter(Phase::Spawn)),
-            check_state_change,
-            rebuild_navmesh.in_schedule(OnExit(Phase::Pick)),
-            uncover_dirt.in_schedule(OnEnter(Phase::Pick)),
-            remove_highlight.in_schedule(OnExit(Phase::Build)),
-            PickSelectedTower::pick_building.in_set(OnUpdate(Phase::Pick)),
-            next_level.in_schedule(OnExit(Phase::Spawn)),
-            Slow::change.in_set(OnUpdate(Phase::Spawn)),
-            LaserAttack::update_multiple_targets,
-            SelectedTower::selection,
-            RemoveSelectedTower::remove,
-            RefineAndPickSelectedTower::refine_and_pick,
-            cursor_over_gui,
-            UpdateFulfillableSpecialTowerRecipes::fire.in_schedule(OnExit(Phase::Build)),
-            UpdateFulfillableSpecialTowerRecipes::fire.in_schedule(OnExit(Phase::Pick)),
-        ))
-        .add_systems((
-            UpdateFulfillableSpecialTowerRecipes::run,
-            CombineSelectedTower::run,
-        ))
-        .run();
-}
-
-const MAP_WIDTH: u32 = 4 * 4; // Originally 30 * 4
-const MAP_HEIGHT: u32 = 4 * 4; // Originally 22 * 4
-
-fn startup(
-    mut commands: Commands,
-    mut meshes: ResMut<Assets<Mesh>>,
-    mut mats: ResMut<Assets<StandardMaterial>>,
-    mut build_grid: ResMut<BuildGrid>,
-) {
-    // Perfect isometric rotation
-    let mut transform =
-        Transform::from_rotation(Quat::from_euler(EulerRot::XYZ, 0., 45_f32.to_radians(), 0.));
-    transform.rotate_local_x(-35.264_f32.to_radians());
-    // Imperfect camera placement wherever
-    transform.translation = Vec3::new(
-        MAP_WIDTH as f32 * 1.2,
-        MAP_WIDTH as f32 * 0.5,
-        MAP_HEIGHT as f32 * 1.2,
-    );
-    let camera = Camera3dBundle {
-        transform,
-        ..default()
+    } else {
+        Replay::Off
+    };
+    println!("Game seed: {seed}");
+    // A replay plays itself, so skip the main menu
+    let first_screen = if matches!(replay, Replay::Playback(_)) {
+        AppState::InGame
+    } else {
+        AppState::MainMenu
     };
-    commands.spawn(camera);
 
-    let tilemap = [Navability::Navable; ((MAP_WIDTH * MAP_HEIGHT) as usize)];
-    let navability = |pos: UVec2| tilemap[(pos.y * MAP_WIDTH + pos.x) as usize];
-    commands.spawn((
-        PbrBundle {
-            mesh: meshes.add(
-                Plane {
-                    size: MAP_WIDTH as f32,
+    let mut app = App::new();
+    app.insert_resource(AmbientLight {
+        brightness: 1.0,
+        color: Color::WHITE,
+    })
+    .insert_resource(ClearColor(CLEAR))
+    // External plugins
+    .add_plugins(
+        DefaultPlugins
+            .set(WindowPlugin {
+                primary_window: Some(Window {
+                    resolution: WindowResolution::new(WINDOW_HEIGHT * RESOLUTION, WINDOW_HEIGHT),
+                    title: "GEM TD".to_string(),
                     ..default()
-                }
-                .into(),
-            ),
-            material: mats.add(Color::DARK_GREEN.into()),
-            transform: Transform::from_xyz(MAP_WIDTH as f32 / 2., 0., MAP_HEIGHT as f32 / 2.),
-            ..default()
-        },
-        Collider::cuboid(MAP_WIDTH as f32 / 2., 0.01, MAP_HEIGHT as f32 / 2.),
-        Navmeshes::generate(
-            [MAP_WIDTH, MAP_HEIGHT].into(),
-            Vec2::new(1., 1.),
-            navability,
-            [CREEP_CLEARANCE],
-        )
-        .unwrap(),
-    ));
-
-    commands.spawn((CreepSpawner::default(),));
-
-    build_grid.insert(UVec2::new(0, 15));
-    build_grid.insert(UVec2::new(0, 14));
-    build_grid.insert(UVec2::new(1, 14));
-    build_grid.insert(UVec2::new(1, 15));
-    build_grid.insert(UVec2::new(15, 0));
-    build_grid.insert(UVec2::new(14, 0));
-    build_grid.insert(UVec2::new(14, 1));
-    build_grid.insert(UVec2::new(15, 1));
-}
-
-#[derive(Clone, Debug, Default, Eq, Hash, PartialEq, States)]
-pub enum Phase {
-    #[default]
-    Build,
-    Pick,
-    Spawn,
-}
-
-fn check_state_change(state: Res<State<Phase>>) {
-    if state.is_changed() {
-        // println!("State changed to {state:?}");
+                }),
+                ..default()
+            })
+            .set(ImagePlugin::default_nearest()),
+    )
+    .add_plugin(DebugLinesPlugin::with_depth_test(true))
+    .add_plugin(RapierPhysicsPlugin::<NoUserData>::default())
+    // Internal plugins
+    .insert_resource(GameRng::new(seed))
+    .insert_resource(config.keymap.clone())
+    .insert_resource(config.gem_chances.clone().validated())
+    .insert_resource(config)
+    .insert_resource(replay)
+    .insert_resource(State(first_screen))
+    .add_plugin(GemTdPlugin);
+    if !matches!(app.world.resource::<Replay>(), Replay::Off) {
+        // Recorded and replayed runs advance the clock by a fixed step, see replay::fixed_clock
+        app.insert_resource(TimeUpdateStrategy::ManualInstant(Instant::now()));
     }
-}
-
-#[derive(Resource, Clone, Copy, Deref, DerefMut)]
-pub struct CurrentLevel(u32);
-
-impl Default for CurrentLevel {
-    fn default() -> Self {
-        Self(1)
-    }
-}
-
-pub fn next_level(mut level: ResMut<CurrentLevel>) {
-    **level += 1;
+    #[cfg(feature = "dev-tools")]
+    app.add_plugin(gem_td::debug::DevToolsPlugin);
+    app.run();
 }
@@ -0,0 +1,402 @@
+use std::path::Path;
+
+use bevy::{app::AppExit, prelude::*, ui::FocusPolicy};
+
+use crate::{
+    config::{Config, Keymap},
+    save::{GameLoaded, LoadGame, SaveGame, SAVE_FILE},
+    AppState,
+};
+
+pub struct MenuPlugin;
+
+impl Plugin for MenuPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Rebinding>()
+            .init_resource::<SettingsReturn>()
+            .add_systems((
+                Menu::spawn_main.in_schedule(OnEnter(AppState::MainMenu)),
+                Menu::despawn.in_schedule(OnExit(AppState::MainMenu)),
+                Menu::spawn_settings.in_schedule(OnEnter(AppState::Settings)),
+                Menu::despawn.in_schedule(OnExit(AppState::Settings)),
+                Menu::save_settings.in_schedule(OnExit(AppState::Settings)),
+                Menu::spawn_pause.in_schedule(OnEnter(AppState::Paused)),
+                Menu::despawn.in_schedule(OnExit(AppState::Paused)),
+                MenuButton::interaction,
+                MenuButton::update_labels,
+                Menu::back.before(Rebinding::rebind),
+                Rebinding::rebind,
+                Menu::continue_loaded,
+                sync_keymap,
+            ));
+    }
+}
+
+/// Root of whichever menu screen is open
+#[derive(Component)]
+pub struct Menu;
+
+/// Screen to go back to when leaving the settings, they can be opened from the main menu and the
+/// pause menu
+#[derive(Resource, Default)]
+pub struct SettingsReturn(AppState);
+
+/// Index into [`Keymap::bindings_mut`] of the action waiting for a new key
+#[derive(Resource, Default, Deref, DerefMut)]
+pub struct Rebinding(Option<usize>);
+
+impl Rebinding {
+    /// Assigns the next key pressed to the action being rebound, escape cancels
+    fn rebind(
+        keys: Res<Input<KeyCode>>,
+        mut rebinding: ResMut<Rebinding>,
+        mut config: ResMut<Config>,
+    ) {
+        let Some(index) = **rebinding else {
+            return;
+        };
+        let Some(&key) = keys.get_just_pressed().next() else {
+            return;
+        };
+        if key != KeyCode::Escape {
+            config.keymap.rebind(index, key);
+        }
+        **rebinding = None;
+    }
+}
+
+#[derive(Component, Clone, Copy, PartialEq)]
+pub enum MenuButton {
+    NewGame,
+    Continue,
+    Settings,
+    Quit,
+    Resume,
+    Save,
+    Back,
+    VolumeDown,
+    /// Only shows the current volume
+    Volume,
+    VolumeUp,
+    DamageNumbers,
+    #[cfg(feature = "dev-tools")]
+    DebugOverlay,
+    Rebind(usize),
+}
+
+#[derive(Component)]
+struct MenuButtonText(MenuButton);
+
+impl MenuButton {
+    fn label(self, config: &Config, rebinding: &Rebinding) -> String {
+        let on_off = |on: bool| if on { "on" } else { "off" };
+        match self {
+            MenuButton::NewGame => "New game".to_string(),
+            MenuButton::Continue => "Continue".to_string(),
+            MenuButton::Settings => "Settings".to_string(),
+            MenuButton::Quit => "Quit".to_string(),
+            MenuButton::Resume => "Resume".to_string(),
+            MenuButton::Save => "Save game".to_string(),
+            MenuButton::Back => "Back".to_string(),
+            MenuButton::VolumeDown => "-".to_string(),
+            MenuButton::Volume => format!("Volume {:.0}%", config.volume * 100.),
+            MenuButton::VolumeUp => "+".to_string(),
+            MenuButton::DamageNumbers => {
+                format!("Damage numbers: {}", on_off(config.damage_numbers))
+            }
+            #[cfg(feature = "dev-tools")]
+            MenuButton::DebugOverlay => {
+                format!("Debug overlay: {}", on_off(config.debug_overlay))
+            }
+            MenuButton::Rebind(index) => match config.keymap.binding(index) {
+                Some((name, _)) if **rebinding == Some(index) => format!("{name}: press a key"),
+                Some((name, key)) => format!("{name}: {key:?}"),
+                None => String::new(),
+            },
+        }
+    }
+
+    fn interaction(
+        mut exit: EventWriter<AppExit>,
+        mut load: EventWriter<LoadGame>,
+        mut save: EventWriter<SaveGame>,
+        state: Res<State<AppState>>,
+        mut next_state: ResMut<NextState<AppState>>,
+        mut settings_return: ResMut<SettingsReturn>,
+        mut config: ResMut<Config>,
+        mut rebinding: ResMut<Rebinding>,
+        buttons: Query<(&Interaction, &MenuButton), Changed<Interaction>>,
+    ) {
+        for (interaction, button) in &buttons {
+            if *interaction != Interaction::Clicked {
+                continue;
+            }
+            match button {
+                MenuButton::NewGame | MenuButton::Resume => next_state.set(AppState::InGame),
+                // The game only starts once the save is restored, see Menu::continue_loaded
+                MenuButton::Continue => load.send(LoadGame),
+                MenuButton::Settings => {
+                    settings_return.0 = state.0;
+                    next_state.set(AppState::Settings);
+                }
+                MenuButton::Quit => exit.send(AppExit),
+                MenuButton::Save => save.send(SaveGame),
+                MenuButton::Back => next_state.set(settings_return.0),
+                MenuButton::VolumeDown => {
+                    config.volume = ((config.volume * 10.).round() - 1.).max(0.) / 10.;
+                }
+                MenuButton::Volume => {}
+                MenuButton::VolumeUp => {
+                    config.volume = ((config.volume * 10.).round() + 1.).min(10.) / 10.;
+                }
+                MenuButton::DamageNumbers => config.damage_numbers = !config.damage_numbers,
+                #[cfg(feature = "dev-tools")]
+                MenuButton::DebugOverlay => config.debug_overlay = !config.debug_overlay,
+                MenuButton::Rebind(index) => **rebinding = Some(*index),
+            }
+        }
+    }
+
+    fn update_labels(
+        config: Res<Config>,
+        rebinding: Res<Rebinding>,
+        mut texts: Query<(&mut Text, &MenuButtonText)>,
+    ) {
+        if !config.is_changed() && !rebinding.is_changed() {
+            return;
+        }
+        for (mut text, MenuButtonText(button)) in &mut texts {
+            text.sections[0].value = button.label(&config, &rebinding);
+        }
+    }
+}
+
+impl Menu {
+    fn spawn_main(
+        mut commands: Commands,
+        ass: Res<AssetServer>,
+        config: Res<Config>,
+        rebinding: Res<Rebinding>,
+    ) {
+        let mut rows = vec![vec![MenuButton::NewGame]];
+        if Path::new(SAVE_FILE).exists() {
+            rows.push(vec![MenuButton::Continue]);
+        }
+        rows.push(vec![MenuButton::Settings]);
+        rows.push(vec![MenuButton::Quit]);
+        Self::spawn(&mut commands, &ass, &config, &rebinding, "GEM TD", &rows);
+    }
+
+    fn spawn_settings(
+        mut commands: Commands,
+        ass: Res<AssetServer>,
+        config: Res<Config>,
+        rebinding: Res<Rebinding>,
+    ) {
+        let bindings = config.keymap.clone().bindings_mut().len();
+        Self::spawn(
+            &mut commands,
+            &ass,
+            &config,
+            &rebinding,
+            "Settings",
+            &[
+                vec![
+                    MenuButton::VolumeDown,
+                    MenuButton::Volume,
+                    MenuButton::VolumeUp,
+                ],
+                vec![MenuButton::DamageNumbers],
+                #[cfg(feature = "dev-tools")]
+                vec![MenuButton::DebugOverlay],
+                (0..bindings).map(MenuButton::Rebind).collect(),
+                vec![MenuButton::Back],
+            ],
+        );
+    }
+
+    fn spawn_pause(
+        mut commands: Commands,
+        ass: Res<AssetServer>,
+        config: Res<Config>,
+        rebinding: Res<Rebinding>,
+    ) {
+        Self::spawn(
+            &mut commands,
+            &ass,
+            &config,
+            &rebinding,
+            "Paused",
+            &[
+                vec![MenuButton::Resume],
+                vec![MenuButton::Save],
+                vec![MenuButton::Settings],
+                vec![MenuButton::Quit],
+            ],
+        );
+    }
+
+    /// Spawns a screen covering the game with a title and rows of buttons
+    fn spawn(
+        commands: &mut Commands,
+        ass: &AssetServer,
+        config: &Config,
+        rebinding: &Rebinding,
+        title: &str,
+        rows: &[Vec<MenuButton>],
+    ) {
+        let font = ass.load("Mukta-Regular.ttf");
+        let root = commands
+            .spawn((
+                NodeBundle {
+                    style: Style {
+                        position_type: PositionType::Absolute,
+                        flex_direction: FlexDirection::Column,
+                        align_items: AlignItems::Center,
+                        justify_content: JustifyContent::Center,
+                        size: Size::all(Val::Percent(100.)),
+                        ..default()
+                    },
+                    background_color: Color::rgba(0., 0., 0., 0.8).into(),
+                    focus_policy: FocusPolicy::Block,
+                    z_index: ZIndex::Global(10),
+                    ..default()
+                },
+                Menu,
+            ))
+            .id();
+
+        let title = commands
+            .spawn(TextBundle {
+                text: Text::from_section(
+                    title,
+                    TextStyle {
+                        font: font.clone(),
+                        font_size: 60.,
+                        color: Color::ANTIQUE_WHITE,
+                    },
+                ),
+                style: Style {
+                    margin: UiRect::bottom(Val::Px(20.)),
+                    ..default()
+                },
+                ..default()
+            })
+            .id();
+        commands.entity(root).add_child(title);
+
+        for row in rows {
+            // Long rows like the hotkeys wrap into a grid of smaller buttons
+            let compact = row.len() > 3;
+            let (size, font_size) = if compact {
+                (Size::new(Val::Px(250.), Val::Px(32.)), 20.)
+            } else if row.len() > 1 {
+                (Size::new(Val::Px(160.), Val::Px(45.)), 30.)
+            } else {
+                (Size::new(Val::Px(250.), Val::Px(45.)), 30.)
+            };
+            let row_node = commands
+                .spawn(NodeBundle {
+                    style: Style {
+                        flex_direction: FlexDirection::Row,
+                        flex_wrap: FlexWrap::Wrap,
+                        justify_content: JustifyContent::Center,
+                        max_size: Size::width(Val::Px(800.)),
+                        ..default()
+                    },
+                    ..default()
+                })
+                .id();
+            for &button in row {
+                let text = commands
+                    .spawn((
+                        TextBundle::from_section(
+                            button.label(config, rebinding),
+                            TextStyle {
+                                font: font.clone(),
+                                font_size,
+                                color: Color::BLACK,
+                            },
+                        ),
+                        MenuButtonText(button),
+                    ))
+                    .id();
+                let button = commands
+                    .spawn((
+                        ButtonBundle {
+                            style: Style {
+                                size,
+                                margin: UiRect::all(Val::Px(4.)),
+                                align_items: AlignItems::Center,
+                                justify_content: JustifyContent::Center,
+                                ..default()
+                            },
+                            background_color: Color::SILVER.into(),
+                            ..default()
+                        },
+                        button,
+                    ))
+                    .add_child(text)
+                    .id();
+                commands.entity(row_node).add_child(button);
+            }
+            commands.entity(root).add_child(row_node);
+        }
+    }
+
+    fn despawn(mut commands: Commands, menus: Query<Entity, With<Menu>>) {
+        for menu in &menus {
+            commands.entity(menu).despawn_recursive();
+        }
+    }
+
+    fn save_settings(config: Res<Config>, mut rebinding: ResMut<Rebinding>) {
+        **rebinding = None;
+        if let Err(err) = config.save() {
+            error!("{err}");
+        }
+    }
+
+    /// Leaves the main menu once a save picked with Continue has been restored, a save that fails to
+    /// load keeps the player on the menu
+    fn continue_loaded(
+        mut loaded: EventReader<GameLoaded>,
+        state: Res<State<AppState>>,
+        mut next_state: ResMut<NextState<AppState>>,
+    ) {
+        if loaded.is_empty() {
+            return;
+        }
+        loaded.clear();
+        if state.0 == AppState::MainMenu {
+            next_state.set(AppState::InGame);
+        }
+    }
+
+    /// Opens the pause menu from the game and goes back out of any menu
+    fn back(
+        keys: Res<Input<KeyCode>>,
+        keymap: Res<Keymap>,
+        rebinding: Res<Rebinding>,
+        state: Res<State<AppState>>,
+        settings_return: Res<SettingsReturn>,
+        mut next_state: ResMut<NextState<AppState>>,
+    ) {
+        if rebinding.is_some() || !keys.just_pressed(keymap.menu) {
+            return;
+        }
+        match state.0 {
+            AppState::InGame => next_state.set(AppState::Paused),
+            AppState::Paused => next_state.set(AppState::InGame),
+            AppState::Settings => next_state.set(settings_return.0),
+            AppState::MainMenu => {}
+        }
+    }
+}
+
+/// Keeps the [`Keymap`] systems read in sync with the one being edited in the settings
+fn sync_keymap(config: Res<Config>, mut keymap: ResMut<Keymap>) {
+    if config.is_changed() {
+        *keymap = config.keymap.clone();
+    }
+}
@@ -0,0 +1,201 @@
+use std::{
+    collections::VecDeque,
+    fmt::Display,
+    fs::{self, File},
+    io::{LineWriter, Write},
+    path::Path,
+    time::Duration,
+};
+
+use bevy::{prelude::*, time::TimeUpdateStrategy};
+use serde::{Deserialize, Serialize};
+
+use crate::{controls::GameCommand, headless::TIMESTEP, AppState, CurrentLevel, Phase};
+
+/// Bump whenever the layout of [`ReplayHeader`] or [`RecordedCommand`] changes
+pub const REPLAY_VERSION: u32 = 3;
+
+/// First line of a replay file, everything after it is one [`RecordedCommand`] per line
+#[derive(Serialize, Deserialize)]
+pub struct ReplayHeader {
+    pub version: u32,
+    pub seed: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct RecordedCommand {
+    /// Game time since the wave and phase the command was given in started, unlike frames this
+    /// doesn't depend on the game speed or on time spent paused
+    pub offset: Duration,
+    pub level: u32,
+    pub phase: Phase,
+    pub command: GameCommand,
+}
+
+impl RecordedCommand {
+    /// Orders the moment a command was given by wave first, since frame counts drift between runs
+    fn stamp(level: u32, phase: &Phase) -> (u32, u8) {
+        (
+            level,
+            match phase {
+                Phase::Build => 0,
+                Phase::Pick => 1,
+                Phase::Spawn => 2,
+            },
+        )
+    }
+}
+
+/// Game time the current wave and phase started at
+#[derive(Default)]
+pub struct PhaseStart {
+    stamp: (u32, u8),
+    elapsed: Duration,
+}
+
+impl PhaseStart {
+    /// Game time since the wave and phase of `stamp` started, the first frame with a new stamp
+    /// starts counting again from zero
+    fn offset(&mut self, stamp: (u32, u8), elapsed: Duration) -> Duration {
+        if self.stamp != stamp {
+            *self = Self { stamp, elapsed };
+        }
+        elapsed.saturating_sub(self.elapsed)
+    }
+}
+
+pub enum ReplayError {
+    Io(std::io::Error),
+    Serialize(ron::Error),
+    Deserialize(ron::error::SpannedError),
+    Version { found: u32 },
+    Empty,
+}
+
+impl Display for ReplayError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ReplayError::Io(err) => write!(f, "Could not access replay: {err}"),
+            ReplayError::Serialize(err) => write!(f, "Could not serialize command: {err}"),
+            ReplayError::Deserialize(err) => write!(f, "Could not read replay: {err}"),
+            ReplayError::Version { found } => write!(
+                f,
+                "Replay has version {found} but this build only supports version {REPLAY_VERSION}"
+            ),
+            ReplayError::Empty => write!(f, "Replay file is empty"),
+        }
+    }
+}
+
+#[derive(Default, Resource)]
+pub enum Replay {
+    #[default]
+    Off,
+    Recording(LineWriter<File>),
+    Playback(VecDeque<RecordedCommand>),
+}
+
+/// Run condition for systems that turn player input into commands, during playback the commands
+/// come from the replay instead, and nothing reaches the game while a menu is open
+pub fn live_input(replay: Res<Replay>, state: Res<State<AppState>>) -> bool {
+    !matches!(*replay, Replay::Playback(_)) && state.0 == AppState::InGame
+}
+
+impl Replay {
+    pub fn record(path: &Path, seed: u64) -> Result<Self, ReplayError> {
+        let mut file = LineWriter::new(File::create(path).map_err(ReplayError::Io)?);
+        Self::write_line(
+            &mut file,
+            &ReplayHeader {
+                version: REPLAY_VERSION,
+                seed,
+            },
+        )?;
+        Ok(Self::Recording(file))
+    }
+
+    /// Reads a replay, returning the seed the recorded run was started with
+    pub fn playback(path: &Path) -> Result<(Self, u64), ReplayError> {
+        let text = fs::read_to_string(path).map_err(ReplayError::Io)?;
+        let mut lines = text.lines().filter(|line| !line.trim().is_empty());
+        let header: ReplayHeader = ron::from_str(lines.next().ok_or(ReplayError::Empty)?)
+            .map_err(ReplayError::Deserialize)?;
+        if header.version != REPLAY_VERSION {
+            return Err(ReplayError::Version {
+                found: header.version,
+            });
+        }
+        let commands = lines
+            .map(ron::from_str)
+            .collect::<Result<_, _>>()
+            .map_err(ReplayError::Deserialize)?;
+        Ok((Self::Playback(commands), header.seed))
+    }
+
+    fn write_line(file: &mut LineWriter<File>, value: &impl Serialize) -> Result<(), ReplayError> {
+        let line = ron::to_string(value).map_err(ReplayError::Serialize)?;
+        writeln!(file, "{line}").map_err(ReplayError::Io)
+    }
+
+    pub fn record_commands(
+        mut replay: ResMut<Replay>,
+        mut commands: EventReader<GameCommand>,
+        mut start: Local<PhaseStart>,
+        time: Res<Time>,
+        level: Res<CurrentLevel>,
+        phase: Res<State<Phase>>,
+    ) {
+        let Replay::Recording(file) = replay.as_mut() else {
+            return;
+        };
+        let offset = start.offset(RecordedCommand::stamp(**level, &phase.0), time.elapsed());
+        for command in commands.iter() {
+            let recorded = RecordedCommand {
+                offset,
+                level: **level,
+                phase: phase.0.clone(),
+                command: command.clone(),
+            };
+            if let Err(err) = Self::write_line(file, &recorded) {
+                error!("{err}");
+            }
+        }
+    }
+
+    pub fn play_commands(
+        mut replay: ResMut<Replay>,
+        mut commands: EventWriter<GameCommand>,
+        mut start: Local<PhaseStart>,
+        time: Res<Time>,
+        level: Res<CurrentLevel>,
+        phase: Res<State<Phase>>,
+    ) {
+        let Replay::Playback(recorded) = replay.as_mut() else {
+            return;
+        };
+        let now = RecordedCommand::stamp(**level, &phase.0);
+        let offset = start.offset(now, time.elapsed());
+        while let Some(next) = recorded.front() {
+            let stamp = RecordedCommand::stamp(next.level, &next.phase);
+            // Wait for the game to reach the same wave and phase, then for the same offset into
+            // it. Commands left over from a phase that ended sooner than it did while recording
+            // are sent right away instead of drifting further into the game
+            if stamp > now || (stamp == now && next.offset > offset) {
+                break;
+            }
+            commands.send(recorded.pop_front().unwrap().command);
+        }
+    }
+}
+
+/// Steps the game clock by a fixed [`TIMESTEP`] every frame while recording or playing back, so
+/// the same commands at the same offsets see the same game no matter the frame rate. Needs a
+/// [`TimeUpdateStrategy::ManualInstant`] to take effect
+pub fn fixed_clock(replay: Res<Replay>, mut strategy: ResMut<TimeUpdateStrategy>) {
+    if matches!(*replay, Replay::Off) {
+        return;
+    }
+    if let TimeUpdateStrategy::ManualInstant(now) = strategy.as_mut() {
+        *now += TIMESTEP;
+    }
+}
@@ -0,0 +1,243 @@
+use std::{fmt::Display, fs};
+
+use bevy::{math::Vec3Swizzles, prelude::*};
+use seldom_map_nav::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    common::{Builds, GameRng},
+    config::Keymap,
+    controls::{SelectedTower, TileHighlight},
+    creeps::{Creep, CreepSpawner},
+    progress_bar::ProgressBar,
+    towers::{BuildGrid, GemDroughts, GemType, JustBuilt, RandomLevel, Tower},
+    CurrentLevel, Phase, MAP_HEIGHT, MAP_WIDTH,
+};
+
+pub const SAVE_FILE: &str = "savegame.ron";
+/// Bump whenever the layout of [`SaveFile`] changes so old saves are rejected instead of misread
+pub const SAVE_VERSION: u32 = 3;
+
+#[derive(Serialize, Deserialize)]
+pub struct SaveFile {
+    pub version: u32,
+    pub level: u32,
+    pub builds: u32,
+    pub random_level: u32,
+    pub seed: u64,
+    pub rng_state: u64,
+    /// Gems built since each type last came up, see [`GemDroughts`]
+    pub gem_droughts: Vec<(GemType, u32)>,
+    pub phase: Phase,
+    pub build_grid: Vec<UVec2>,
+    pub towers: Vec<SavedTower>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SavedTower {
+    pub tower: Tower,
+    pub position: Vec2,
+    pub just_built: bool,
+}
+
+/// Sent when saving or loading fails so the player can be told why
+pub enum SaveError {
+    Io(std::io::Error),
+    Serialize(ron::Error),
+    Deserialize(ron::error::SpannedError),
+    Version {
+        found: u32,
+    },
+    /// Creeps aren't saved, so a game can only be saved and loaded between waves
+    DuringWave,
+    InvalidTile(UVec2),
+}
+
+impl Display for SaveError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SaveError::Io(err) => write!(f, "Could not access {SAVE_FILE}: {err}"),
+            SaveError::Serialize(err) => write!(f, "Could not serialize save: {err}"),
+            SaveError::Deserialize(err) => write!(f, "Could not read {SAVE_FILE}: {err}"),
+            SaveError::Version { found } => write!(
+                f,
+                "Save file has version {found} but this build only supports version {SAVE_VERSION}"
+            ),
+            SaveError::DuringWave => write!(f, "Games can only be saved and loaded between waves"),
+            SaveError::InvalidTile(tile) => write!(f, "Save file has tile {tile} outside the map"),
+        }
+    }
+}
+
+impl SaveFile {
+    pub fn write(&self) -> Result<(), SaveError> {
+        let text = ron::ser::to_string_pretty(self, ron::ser::PrettyConfig::default())
+            .map_err(SaveError::Serialize)?;
+        fs::write(SAVE_FILE, text).map_err(SaveError::Io)
+    }
+
+    pub fn read() -> Result<Self, SaveError> {
+        let text = fs::read_to_string(SAVE_FILE).map_err(SaveError::Io)?;
+        let file: Self = ron::from_str(&text).map_err(SaveError::Deserialize)?;
+        if file.version != SAVE_VERSION {
+            return Err(SaveError::Version {
+                found: file.version,
+            });
+        }
+        if file.phase == Phase::Spawn {
+            return Err(SaveError::DuringWave);
+        }
+        if let Some(tile) = file
+            .build_grid
+            .iter()
+            .find(|tile| tile.x >= MAP_WIDTH || tile.y >= MAP_HEIGHT)
+        {
+            return Err(SaveError::InvalidTile(*tile));
+        }
+        Ok(file)
+    }
+}
+
+pub fn save_load_hotkeys(
+    keys: Res<Input<KeyCode>>,
+    keymap: Res<Keymap>,
+    mut save: EventWriter<SaveGame>,
+    mut load: EventWriter<LoadGame>,
+) {
+    if keys.just_pressed(keymap.save) {
+        save.send(SaveGame);
+    }
+    if keys.just_pressed(keymap.load) {
+        load.send(LoadGame);
+    }
+}
+
+#[derive(Default)]
+pub struct SaveGame;
+
+impl SaveGame {
+    pub fn save(
+        mut events: EventReader<SaveGame>,
+        mut errors: EventWriter<SaveError>,
+        level: Res<CurrentLevel>,
+        builds: Res<Builds>,
+        random_level: Res<RandomLevel>,
+        rng: Res<GameRng>,
+        droughts: Res<GemDroughts>,
+        phase: Res<State<Phase>>,
+        build_grid: Res<BuildGrid>,
+        towers: Query<(&Tower, &GlobalTransform, Option<&JustBuilt>)>,
+    ) {
+        for _ in events.iter() {
+            if phase.0 == Phase::Spawn {
+                errors.send(SaveError::DuringWave);
+                continue;
+            }
+            let file = SaveFile {
+                version: SAVE_VERSION,
+                level: **level,
+                builds: **builds,
+                random_level: **random_level,
+                seed: rng.seed(),
+                rng_state: rng.state(),
+                gem_droughts: droughts
+                    .iter()
+                    .map(|(typ, missed)| (*typ, *missed))
+                    .collect(),
+                phase: phase.0.clone(),
+                build_grid: build_grid.iter().copied().collect(),
+                towers: towers
+                    .iter()
+                    .map(|(tower, transform, just_built)| SavedTower {
+                        tower: *tower,
+                        position: transform.translation().xz(),
+                        just_built: just_built.is_some(),
+                    })
+                    .collect(),
+            };
+            if let Err(err) = file.write() {
+                errors.send(err);
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct LoadGame;
+
+/// Sent after a save has been restored
+pub struct GameLoaded;
+
+impl LoadGame {
+    pub fn load(
+        mut commands: Commands,
+        mut events: EventReader<LoadGame>,
+        (mut loaded, mut errors): (EventWriter<GameLoaded>, EventWriter<SaveError>),
+        mut meshes: ResMut<Assets<Mesh>>,
+        mut mats: ResMut<Assets<StandardMaterial>>,
+        mut level: ResMut<CurrentLevel>,
+        mut builds: ResMut<Builds>,
+        mut random_level: ResMut<RandomLevel>,
+        mut rng: ResMut<GameRng>,
+        mut droughts: ResMut<GemDroughts>,
+        (mut phase, mut next_phase): (ResMut<State<Phase>>, ResMut<NextState<Phase>>),
+        mut build_grid: ResMut<BuildGrid>,
+        mut spawners: Query<&mut CreepSpawner>,
+        stale: Query<Entity, Or<(With<Tower>, With<Creep>, With<TileHighlight>)>>,
+        bars: Query<(&ProgressBar, &Parent)>,
+        navmeshes: Query<Entity, With<Navmeshes>>,
+    ) {
+        for _ in events.iter() {
+            let file = match SaveFile::read() {
+                Ok(file) => file,
+                Err(err) => {
+                    errors.send(err);
+                    continue;
+                }
+            };
+
+            for entity in &stale {
+                commands.entity(entity).despawn_recursive();
+            }
+            for (_, parent) in &bars {
+                if let Some(entity) = commands.get_entity(**parent) {
+                    entity.despawn_recursive();
+                }
+            }
+            commands.remove_resource::<SelectedTower>();
+
+            **level = file.level;
+            **builds = file.builds;
+            **random_level = file.random_level;
+            *rng = GameRng::from_state(file.seed, file.rng_state);
+            **droughts = file.gem_droughts.into_iter().collect();
+            build_grid.clear();
+            build_grid.extend(file.build_grid);
+            commands
+                .entity(navmeshes.single())
+                .insert(build_grid.navmeshes());
+
+            // The restored towers bring the fulfillable recipes up to date on their own
+            for SavedTower {
+                tower,
+                position,
+                just_built,
+            } in file.towers
+            {
+                let mut entity = tower.spawn(&mut commands, &mut meshes, &mut mats, position);
+                if just_built {
+                    entity.insert(JustBuilt);
+                }
+            }
+
+            // Set the phase directly, a transition would run the enter and exit schedules and
+            // advance the level or reroll the gems that were just restored
+            next_phase.0 = None;
+            phase.0 = file.phase;
+            for mut spawner in &mut spawners {
+                *spawner = CreepSpawner::default();
+            }
+            loaded.send(GameLoaded);
+        }
+    }
+}
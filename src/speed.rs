@@ -0,0 +1,64 @@
+use bevy::prelude::*;
+
+use crate::{config::Keymap, replay::live_input, AppState};
+
+/// Multipliers the game speed steps through
+const SPEEDS: [f32; 3] = [1., 2., 4.];
+
+pub struct GameSpeedPlugin;
+
+impl Plugin for GameSpeedPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<GameSpeed>().add_systems(
+            (
+                // Locked during playback so every replay steps through the game the same way
+                GameSpeed::hotkeys.run_if(live_input),
+                GameSpeed::apply,
+            )
+                .chain()
+                .in_base_set(CoreSet::PreUpdate),
+        );
+    }
+}
+
+/// How fast the game clock runs, everything reading [`Time`] follows it while the camera and UI
+/// use the raw delta so they stay responsive while paused
+#[derive(Resource, Default)]
+pub struct GameSpeed {
+    pub paused: bool,
+    /// Index into [`SPEEDS`]
+    step: usize,
+}
+
+impl GameSpeed {
+    pub fn multiplier(&self) -> f32 {
+        SPEEDS[self.step]
+    }
+
+    /// Goes to the next speed, wrapping back around to normal speed after the fastest
+    pub fn cycle(&mut self) {
+        self.step = (self.step + 1) % SPEEDS.len();
+    }
+
+    fn hotkeys(keys: Res<Input<KeyCode>>, keymap: Res<Keymap>, mut speed: ResMut<GameSpeed>) {
+        if keys.just_pressed(keymap.pause) {
+            speed.paused = !speed.paused;
+        }
+        if keys.just_pressed(keymap.speed) {
+            speed.cycle();
+        }
+    }
+
+    /// Stops the clock while paused or in a menu
+    fn apply(speed: Res<GameSpeed>, state: Res<State<AppState>>, mut time: ResMut<Time>) {
+        if !speed.is_changed() && !state.is_changed() {
+            return;
+        }
+        if speed.paused || state.0 != AppState::InGame {
+            time.pause();
+        } else {
+            time.unpause();
+        }
+        time.set_relative_speed(speed.multiplier());
+    }
+}
@@ -0,0 +1,89 @@
+use std::time::Duration;
+
+use bevy::prelude::*;
+
+use crate::{
+    creeps::{DamageDealt, Dead},
+    towers::{Target, Tower},
+};
+
+#[derive(Clone, Copy, Default)]
+pub struct Tally {
+    pub damage: u64,
+    pub kills: u32,
+    pub overkill: u64,
+    /// Time spent with at least one target
+    pub active: Duration,
+    /// Time spent in waves
+    pub elapsed: Duration,
+}
+
+impl Tally {
+    /// Share of wave time the tower had something to shoot at, from 0 to 1
+    pub fn uptime(&self) -> f32 {
+        if self.elapsed.is_zero() {
+            0.
+        } else {
+            self.active.as_secs_f32() / self.elapsed.as_secs_f32()
+        }
+    }
+}
+
+/// What a tower has done over its lifetime and during the current or last wave
+#[derive(Component, Default)]
+pub struct TowerStats {
+    pub total: Tally,
+    pub wave: Tally,
+}
+
+impl TowerStats {
+    pub fn add(mut commands: Commands, towers: Query<Entity, (Added<Tower>, Without<TowerStats>)>) {
+        for tower in &towers {
+            commands.entity(tower).insert(TowerStats::default());
+        }
+    }
+
+    pub fn record(
+        mut dealt: EventReader<DamageDealt>,
+        mut deaths: EventReader<Dead>,
+        mut towers: Query<&mut TowerStats>,
+    ) {
+        for dealt in dealt.iter() {
+            if let Ok(mut stats) = towers.get_mut(dealt.source) {
+                let stats = &mut *stats;
+                for tally in [&mut stats.total, &mut stats.wave] {
+                    tally.damage += u64::from(dealt.amount);
+                    tally.overkill += u64::from(dealt.overkill);
+                }
+            }
+        }
+        for Dead { killer, .. } in deaths.iter() {
+            if let Ok(mut stats) = towers.get_mut(*killer) {
+                stats.total.kills += 1;
+                stats.wave.kills += 1;
+            }
+        }
+    }
+
+    pub fn uptime(time: Res<Time>, mut towers: Query<(&Target, &mut TowerStats)>) {
+        for (target, mut stats) in &mut towers {
+            let stats = &mut *stats;
+            let active = match target {
+                Target::Single(target) => target.is_some(),
+                Target::Multiple(targets) => !targets.is_empty(),
+            };
+            for tally in [&mut stats.total, &mut stats.wave] {
+                tally.elapsed += time.delta();
+                if active {
+                    tally.active += time.delta();
+                }
+            }
+        }
+    }
+
+    pub fn reset_wave(mut towers: Query<&mut TowerStats>) {
+        for mut stats in &mut towers {
+            stats.wave = Tally::default();
+        }
+    }
+}
@@ -1,13 +1,14 @@
-use std::time::Duration;
-
-use bevy::{
-    prelude::*,
-    utils::{HashMap, HashSet},
+use std::{
+    mem::{discriminant, Discriminant},
+    time::Duration,
 };
 
+use bevy::{prelude::*, utils::HashMap};
+
 use crate::{
-    creeps::{Dead, Hit, HitPoints, Slow, SlowSource},
-    towers::{Cooldown, Tower},
+    common::GameRng,
+    creeps::{DamageEvent, DamageKind, DamageSet, Hit, HitPoints, Slow, SlowSource},
+    towers::{Cooldown, GemQuality, Tower},
     Phase,
 };
 
@@ -15,15 +16,25 @@ pub struct TowerAbilitiesPlugin;
 
 impl Plugin for TowerAbilitiesPlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems((
-            SlowPoisonOnHit::on_hit.in_set(OnUpdate(Phase::Spawn)),
+        // Chained after the plain attack damage so damage events, and with them deaths and the
+        // game RNG, come in the same order every run
+        app.add_systems(
+            (
+                CritOnHit::crit,
+                SplashOnHit::splash,
+                SlowPoisonOnHit::on_hit,
+                SlowPoison::update,
+                SapphireSlowOnHit::on_hit,
+            )
+                .chain()
+                .after(Hit::consume)
+                .in_set(DamageSet::OnHit)
+                .in_set(OnUpdate(Phase::Spawn)),
+        )
+        .add_systems((
             SlowPoison::add.in_set(OnUpdate(Phase::Spawn)),
-            SlowPoison::update.in_set(OnUpdate(Phase::Spawn)),
-            SapphireSlowOnHit::on_hit.in_set(OnUpdate(Phase::Spawn)),
             SapphireSlow::changed.in_set(OnUpdate(Phase::Spawn)),
             SapphireSlow::update.in_set(OnUpdate(Phase::Spawn)),
-            CritOnHit::crit.in_set(OnUpdate(Phase::Spawn)),
-            SplashOnHit::splash.in_set(OnUpdate(Phase::Spawn)),
             Aura::aura_tower_added,
             Aura::aura_tower_removed,
             Aura::tower_added,
@@ -41,17 +52,20 @@ pub struct SlowPoisonOnHit {
 
 #[derive(Component)]
 pub struct SlowPoison {
+    /// Tower that applied the poison
+    source: Entity,
     dps: u32,
     slow: u32,
     duration: Timer,
 }
 
-impl From<SlowPoisonOnHit> for SlowPoison {
-    fn from(value: SlowPoisonOnHit) -> Self {
+impl SlowPoison {
+    fn new(source: Entity, on_hit: SlowPoisonOnHit) -> Self {
         Self {
-            dps: value.dps,
-            slow: value.slow,
-            duration: Timer::from_seconds(value.duration, TimerMode::Once),
+            source,
+            dps: on_hit.dps,
+            slow: on_hit.slow,
+            duration: Timer::from_seconds(on_hit.duration, TimerMode::Once),
         }
     }
 }
@@ -66,7 +80,7 @@ impl SlowPoisonOnHit {
             if let Ok(on_hit) = towers.get(*source) {
                 commands
                     .entity(*target)
-                    .insert(Into::<SlowPoison>::into(*on_hit));
+                    .insert(SlowPoison::new(*source, *on_hit));
             }
         }
     }
@@ -88,18 +102,20 @@ impl SlowPoison {
     }
     pub fn update(
         mut commands: Commands,
-        mut events: EventWriter<Dead>,
+        mut damage: EventWriter<DamageEvent>,
         mut poison_timer: Local<PoisonTimer>,
         time: Res<Time>,
-        mut creeps: Query<(Entity, &mut HitPoints, &mut SlowPoison, &mut Slow)>,
+        mut creeps: Query<(Entity, &mut SlowPoison, &mut Slow)>,
     ) {
         poison_timer.tick(time.delta());
-        for (creep, mut hitpoints, mut poison, mut slow) in &mut creeps {
+        for (creep, mut poison, mut slow) in &mut creeps {
             if poison_timer.just_finished() {
-                hitpoints.sub(poison.dps);
-                if hitpoints.dead() {
-                    events.send(Dead(creep));
-                }
+                damage.send(DamageEvent {
+                    source: poison.source,
+                    target: creep,
+                    amount: poison.dps,
+                    kind: DamageKind::Poison,
+                });
             }
             if poison.duration.tick(time.delta()).finished() && commands.get_entity(creep).is_some()
             {
@@ -172,8 +188,8 @@ pub struct CritOnHit;
 impl CritOnHit {
     fn crit(
         mut hits: EventReader<Hit>,
-        mut deads: EventWriter<Dead>,
-        mut creeps: Query<&mut HitPoints>,
+        mut damage: EventWriter<DamageEvent>,
+        mut rng: ResMut<GameRng>,
         towers: Query<(), With<CritOnHit>>,
     ) {
         for Hit {
@@ -182,13 +198,13 @@ impl CritOnHit {
             value,
         } in hits.iter()
         {
-            if let (Ok(..), Ok(mut creep)) = (towers.get(*source), creeps.get_mut(*target)) {
-                if fastrand::f32() < 0.25 {
-                    creep.sub(*value);
-                    if creep.dead() {
-                        deads.send(Dead(*target));
-                    }
-                }
+            if towers.contains(*source) && rng.f32() < 0.25 {
+                damage.send(DamageEvent {
+                    source: *source,
+                    target: *target,
+                    amount: *value,
+                    kind: DamageKind::Crit,
+                });
             }
         }
     }
@@ -203,9 +219,9 @@ pub struct SplashOnHit {
 impl SplashOnHit {
     fn splash(
         mut hits: EventReader<Hit>,
-        mut dead: EventWriter<Dead>,
+        mut damage: EventWriter<DamageEvent>,
         towers: Query<&SplashOnHit>,
-        mut creeps: Query<(Entity, &GlobalTransform, &mut HitPoints)>,
+        creeps: Query<(Entity, &GlobalTransform), With<HitPoints>>,
     ) {
         for Hit {
             source,
@@ -218,20 +234,20 @@ impl SplashOnHit {
                     Ok(value) => *value.1,
                     Err(_) => continue,
                 };
-                for (creep, _, mut hitpoints) in
-                    creeps.iter_mut().filter(|(creep, transform, _)| {
-                        creep != target
-                            && transform
-                                .translation()
-                                .distance_squared(target_pos.translation())
-                                <= range.powf(2.)
-                    })
-                {
+                for (creep, _) in creeps.iter().filter(|(creep, transform)| {
+                    creep != target
+                        && transform
+                            .translation()
+                            .distance_squared(target_pos.translation())
+                            <= range.powf(2.)
+                }) {
                     #[allow(clippy::cast_sign_loss)]
-                    hitpoints.sub((*value as f32 * multiplier) as u32);
-                    if hitpoints.dead() {
-                        dead.send(Dead(creep));
-                    }
+                    damage.send(DamageEvent {
+                        source: *source,
+                        target: creep,
+                        amount: (*value as f32 * multiplier) as u32,
+                        kind: DamageKind::Splash,
+                    });
                 }
             }
         }
@@ -250,6 +266,20 @@ pub struct Aura {
 }
 
 impl Aura {
+    pub fn opal(quality: GemQuality) -> Self {
+        let (modifier, range) = match quality {
+            GemQuality::Chipped => (10, 8.),
+            GemQuality::Flawed => (15, 9.),
+            GemQuality::Normal => (20, 10.),
+            GemQuality::Flawless => (25, 11.),
+            GemQuality::Perfect => (35, 12.),
+        };
+        Self {
+            typ: AuraType::Opal(modifier),
+            range,
+        }
+    }
+
     fn aura_tower_added(
         aura_changed: Query<(), Changed<Aura>>,
         auras: Query<(&GlobalTransform, &Aura)>,
@@ -287,7 +317,8 @@ impl Aura {
         towers: &mut Query<(Entity, &GlobalTransform, &mut SpeedModifiers)>,
         auras: impl Iterator<Item = (&'a GlobalTransform, &'a Aura)>,
     ) {
-        let mut strongest_auras: HashSet<(Entity, AuraType)> = HashSet::default();
+        let mut strongest_auras: HashMap<(Entity, Discriminant<AuraType>), AuraType> =
+            HashMap::default();
         for (aura_pos, aura) in auras {
             for (tower, tower_pos, _) in towers.iter() {
                 if aura_pos
@@ -295,27 +326,20 @@ impl Aura {
                     .distance_squared(tower_pos.translation())
                     <= aura.range.powf(2.)
                 {
-                    // Tower is within range of aura
-                    if let Some((entity, existing_aura)) = strongest_auras.get(&(tower, aura.typ)) {
-                        // Tower is already affected by an aura of this type
-                        #[allow(irrefutable_let_patterns)]
-                        if let (AuraType::Opal(existing_value), AuraType::Opal(new_value)) =
-                            (existing_aura, aura.typ)
-                        {
-                            // Test if the new value is bigger than the old
-                            if *existing_value < new_value {
-                                strongest_auras.insert((*entity, AuraType::Opal(new_value)));
-                            }
-                        }
-                    } else {
-                        // Tower is not yet affected by an aura of this type
-                        strongest_auras.insert((tower, aura.typ));
+                    // Tower is within range of aura, keep the strongest one of each type
+                    let strongest = strongest_auras
+                        .entry((tower, discriminant(&aura.typ)))
+                        .or_insert(aura.typ);
+                    let (AuraType::Opal(existing_value), AuraType::Opal(new_value)) =
+                        (*strongest, aura.typ);
+                    if existing_value < new_value {
+                        *strongest = aura.typ;
                     }
                 }
             }
         }
         // Apply auras
-        for (tower, aura) in strongest_auras {
+        for ((tower, _), aura) in strongest_auras {
             match aura {
                 AuraType::Opal(modifier) => {
                     if let Ok((_, _, mut modifiers)) = towers.get_mut(tower) {
@@ -341,7 +365,9 @@ impl SpeedModifiers {
     ) {
         for (tower, mut cooldown, modifiers) in &mut modifiers {
             // Make a cooldown timer that starts in a finished state
-            let base_time = tower.get_base_cooldown_time();
+            let Ok(base_time) = tower.get_base_cooldown_time() else {
+                continue;
+            };
             let mut time = base_time;
             for modifier in modifiers.values() {
                 time -= base_time * (*modifier as f32 / 100.);
@@ -4,22 +4,81 @@ use std::{
     time::Duration,
 };
 
-use bevy::{ecs::system::EntityCommands, math::Vec3Swizzles, prelude::*, utils::HashSet};
+use bevy::{
+    ecs::system::EntityCommands,
+    math::Vec3Swizzles,
+    prelude::*,
+    utils::{HashMap, HashSet},
+};
 use bevy_prototype_debug_lines::DebugLines;
 use seldom_map_nav::prelude::*;
+use serde::{Deserialize, Serialize};
 
 use crate::{
-    common::get_squares_from_pos,
+    common::{get_squares_from_pos, GameRng},
     controls::SelectedTower,
-    creeps::{Creep, CreepType, Hit, HitPoints},
+    creeps::{Creep, CreepType, DamageSet, Hit, HitPoints},
+    stats::TowerStats,
     tower_abilities::{
-        Aura, AuraType, CritOnHit, SapphireSlowOnHit, SlowPoisonOnHit, SpeedModifiers, SplashOnHit,
+        Aura, CritOnHit, SapphireSlowOnHit, SlowPoisonOnHit, SpeedModifiers, SplashOnHit,
+        TowerAbilitiesPlugin,
     },
     Phase, CREEP_CLEARANCE, MAP_HEIGHT, MAP_WIDTH,
 };
 
 pub const BASE_TOWER_SPEED: f32 = 1.0;
 
+/// Building, picking, combining and firing towers
+pub struct TowersPlugin;
+
+impl Plugin for TowersPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<PickSelectedTower>()
+            .add_event::<RemoveSelectedTower>()
+            .add_event::<RefineAndPickSelectedTower>()
+            .add_event::<MergeSelectedTower>()
+            .add_event::<UpdateFulfillableSpecialTowerRecipes>()
+            .add_event::<CombineSelectedTower>()
+            .add_event::<IncreaseUpgradeChance>()
+            .add_event::<GameError>()
+            .add_event::<SpecialTowerCombined>()
+            .add_event::<CycleCombineOption>()
+            .init_resource::<BuildGrid>()
+            .init_resource::<RandomLevel>()
+            .init_resource::<GemChances>()
+            .init_resource::<GemDroughts>()
+            .init_resource::<SpecialTowerRecipes>()
+            .init_resource::<FulfillableSpecialTowerRecipes>()
+            .init_resource::<CombineOptions>()
+            .add_plugin(TowerAbilitiesPlugin)
+            .add_systems((
+                LaserAttack::attack.in_set(DamageSet::Attack),
+                LaserAttack::update_multiple_targets,
+                rebuild_navmesh.in_schedule(OnExit(Phase::Pick)),
+                uncover_dirt.in_schedule(OnEnter(Phase::Pick)),
+                PickSelectedTower::pick_building.in_set(OnUpdate(Phase::Pick)),
+                RemoveSelectedTower::remove,
+                RefineAndPickSelectedTower::refine_and_pick,
+                MergeSelectedTower::merge,
+                UpdateFulfillableSpecialTowerRecipes::fire_on_change
+                    .before(UpdateFulfillableSpecialTowerRecipes::run),
+                UpdateFulfillableSpecialTowerRecipes::run,
+                CombineSelectedTower::run,
+                IncreaseUpgradeChance::increase,
+            ))
+            .add_systems((
+                CombineOptions::update.before(CombineSelectedTower::run),
+                CycleCombineOption::cycle.after(CombineOptions::update),
+            ))
+            .add_systems((
+                TowerStats::add,
+                TowerStats::record.in_set(DamageSet::Resolve),
+                TowerStats::uptime.in_set(OnUpdate(Phase::Spawn)),
+                TowerStats::reset_wave.in_schedule(OnEnter(Phase::Spawn)),
+            ));
+    }
+}
+
 #[derive(Component, Clone)]
 pub enum Target {
     Single(Option<Entity>),
@@ -32,6 +91,11 @@ pub struct Cooldown(pub Timer);
 #[derive(Default, Deref, DerefMut, Resource)]
 pub struct BuildGrid(HashSet<UVec2>);
 
+/// Occupied tiles the [`Navmeshes`] next to it were generated from, the [`BuildGrid`] runs ahead of
+/// the navmesh until it is rebuilt after a pick
+#[derive(Component, Clone, Default, Deref)]
+pub struct NavmeshTiles(HashSet<UVec2>);
+
 impl Debug for BuildGrid {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let mut text = String::with_capacity((MAP_WIDTH * MAP_HEIGHT + MAP_HEIGHT) as usize);
@@ -53,16 +117,24 @@ pub fn uncover_dirt(
     mut commands: Commands,
     mut mats: ResMut<Assets<StandardMaterial>>,
     mut meshes: ResMut<Assets<Mesh>>,
+    mut rng: ResMut<GameRng>,
+    mut droughts: ResMut<GemDroughts>,
     random_level: Res<RandomLevel>,
+    chances: Res<GemChances>,
     just_built: Query<(Entity, &GlobalTransform), With<JustBuilt>>,
 ) {
     for (entity, pos) in &just_built {
-        let typ = GemType::random();
+        let typ = chances.roll_type(&mut droughts, &mut rng);
         let gem_tower = Tower::Gem {
             typ,
-            quality: GemQuality::random_with_modifier(**random_level),
+            quality: chances.roll_quality(**random_level, &mut rng),
+        };
+        let (Ok(attack), Ok(cooldown)) = (
+            LaserAttack::try_from(gem_tower),
+            Cooldown::try_from(gem_tower),
+        ) else {
+            continue;
         };
-        let cooldown: Cooldown = gem_tower.into();
         gem_tower.add_abilities(commands.entity(entity).insert((
             meshes.add(gem_tower.into()),
             Transform::from_xyz(
@@ -73,7 +145,7 @@ pub fn uncover_dirt(
             mats.add(typ.into()),
             gem_tower,
             Name::new(gem_tower.to_string()),
-            LaserAttack::from(gem_tower),
+            attack,
             cooldown,
             Target::Single(None),
             SpeedModifiers::default(),
@@ -87,23 +159,46 @@ pub fn rebuild_navmesh(
     navmeshes: Query<Entity, With<Navmeshes>>,
 ) {
     let map = navmeshes.single();
-    let mut tilemap = [Navability::Navable; ((MAP_WIDTH * MAP_HEIGHT) as usize)];
-    for pos in build_grid.iter() {
-        tilemap[(pos.y * MAP_WIDTH + pos.x) as usize] = Navability::Solid;
-    }
-    let navability = |pos: UVec2| tilemap[(pos.y * MAP_WIDTH + pos.x) as usize];
-    commands.entity(map).insert(
-        Navmeshes::generate(
+    commands.entity(map).insert(build_grid.navmeshes());
+}
+
+impl BuildGrid {
+    /// Generates navmeshes where every occupied tile is solid, along with the tiles they were
+    /// generated from
+    pub fn navmeshes(&self) -> (Navmeshes, NavmeshTiles) {
+        let mut tilemap = [Navability::Navable; ((MAP_WIDTH * MAP_HEIGHT) as usize)];
+        for pos in self.iter() {
+            tilemap[(pos.y * MAP_WIDTH + pos.x) as usize] = Navability::Solid;
+        }
+        let navability = |pos: UVec2| tilemap[(pos.y * MAP_WIDTH + pos.x) as usize];
+        let navmeshes = Navmeshes::generate(
             [MAP_WIDTH, MAP_HEIGHT].into(),
             Vec2::new(1., 1.),
             navability,
             [CREEP_CLEARANCE],
         )
-        .unwrap(),
-    );
+        .unwrap();
+        (navmeshes, NavmeshTiles(self.0.clone()))
+    }
+
+    /// Claims the four squares around a tower tile, returns false without claiming anything if
+    /// one of them is taken or off the map
+    pub fn occupy(&mut self, tile: UVec2) -> bool {
+        if tile.x == 0 || tile.y == 0 || tile.x >= MAP_WIDTH || tile.y >= MAP_HEIGHT {
+            return false;
+        }
+        #[allow(clippy::cast_sign_loss)]
+        let squares = get_squares_from_pos(tile.as_vec2())
+            .map(|pos| UVec2::new((pos.x - 0.5) as u32, (pos.y - 0.5) as u32));
+        if squares.iter().any(|square| self.contains(square)) {
+            return false;
+        }
+        self.extend(squares);
+        true
+    }
 }
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq, Reflect, FromReflect)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Reflect, FromReflect, Serialize, Deserialize)]
 pub enum GemType {
     Emerald,
     Ruby,
@@ -115,7 +210,9 @@ pub enum GemType {
     Topaz,
 }
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq, Reflect, FromReflect)]
+#[derive(
+    Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Reflect, FromReflect, Serialize, Deserialize,
+)]
 pub enum GemQuality {
     Chipped,
     Flawed,
@@ -125,57 +222,140 @@ pub enum GemQuality {
 }
 
 impl GemQuality {
-    #[allow(clippy::cast_sign_loss)]
-    pub fn random_with_modifier(level: u32) -> Self {
+    /// Default chance in percent of rolling each quality at an upgrade chance level
+    pub fn chances(level: u32) -> &'static [(Self, u32)] {
+        use GemQuality::*;
         match level {
-            0 => Self::Chipped,
-            1 => match (fastrand::f32() * 100.) as u32 {
-                0..=69 => Self::Chipped,
-                _ => Self::Flawed,
-            },
-            2 => match (fastrand::f32() * 100.) as u32 {
-                0..=59 => Self::Chipped,
-                60..=89 => Self::Flawed,
-                _ => Self::Normal,
-            },
-            3 => match (fastrand::f32() * 100.) as u32 {
-                0..=49 => Self::Chipped,
-                50..=79 => Self::Flawed,
-                _ => Self::Normal,
-            },
-            4 => match (fastrand::f32() * 100.) as u32 {
-                0..=39 => Self::Chipped,
-                40..=69 => Self::Flawed,
-                70..=89 => Self::Normal,
-                _ => Self::Flawless,
-            },
-            5 => match (fastrand::f32() * 100.) as u32 {
-                0..=29 => Self::Chipped,
-                30..=59 => Self::Flawed,
-                60..=89 => Self::Normal,
-                _ => Self::Flawless,
-            },
-            6 => match (fastrand::f32() * 100.) as u32 {
-                0..=19 => Self::Chipped,
-                20..=49 => Self::Flawed,
-                50..=79 => Self::Normal,
-                _ => Self::Flawless,
-            },
-            7 => match (fastrand::f32() * 100.) as u32 {
-                0..=9 => Self::Chipped,
-                10..=39 => Self::Flawed,
-                40..=69 => Self::Normal,
-                _ => Self::Flawless,
-            },
-            _ => match (fastrand::f32() * 100.) as u32 {
-                0..=29 => Self::Flawed,
-                30..=59 => Self::Normal,
-                60..=89 => Self::Flawless,
-                _ => Self::Perfect,
-            },
+            0 => &[(Chipped, 100)],
+            1 => &[(Chipped, 70), (Flawed, 30)],
+            2 => &[(Chipped, 60), (Flawed, 30), (Normal, 10)],
+            3 => &[(Chipped, 50), (Flawed, 30), (Normal, 20)],
+            4 => &[(Chipped, 40), (Flawed, 30), (Normal, 20), (Flawless, 10)],
+            5 => &[(Chipped, 30), (Flawed, 30), (Normal, 30), (Flawless, 10)],
+            6 => &[(Chipped, 20), (Flawed, 30), (Normal, 30), (Flawless, 20)],
+            7 => &[(Chipped, 10), (Flawed, 30), (Normal, 30), (Flawless, 30)],
+            _ => &[(Flawed, 30), (Normal, 30), (Flawless, 30), (Perfect, 10)],
+        }
+    }
+}
+
+/// Upgrade chance levels [`GemQuality::chances`] has different odds for
+const DEFAULT_QUALITY_LEVELS: u32 = 9;
+
+/// Odds of the gems that come out of building a tower, read from the config
+#[derive(Resource, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GemChances {
+    /// Relative weight of every gem type that can be built
+    pub types: Vec<(GemType, u32)>,
+    /// Weights of each quality by upgrade chance level, levels past the end use the last table
+    pub qualities: Vec<Vec<(GemQuality, u32)>>,
+    /// Raises the odds of gem types that haven't been built in a while
+    pub bad_luck_protection: bool,
+}
+
+impl Default for GemChances {
+    fn default() -> Self {
+        Self {
+            types: GemType::CANDIDATES.map(|typ| (typ, 1)).to_vec(),
+            qualities: (0..DEFAULT_QUALITY_LEVELS)
+                .map(|level| GemQuality::chances(level).to_vec())
+                .collect(),
+            bad_luck_protection: false,
+        }
+    }
+}
+
+impl GemChances {
+    /// Replaces tables that can't be rolled on, like an empty list or only zero weights, with the
+    /// defaults
+    pub fn validated(mut self) -> Self {
+        let default = Self::default();
+        if self.types.iter().all(|(_, weight)| *weight == 0) {
+            self.types = default.types;
+        }
+        if self.qualities.is_empty()
+            || self
+                .qualities
+                .iter()
+                .any(|table| table.iter().all(|(_, weight)| *weight == 0))
+        {
+            self.qualities = default.qualities;
+        }
+        self
+    }
+
+    /// Gem type weights with the bad luck protection applied, every gem built without a type
+    /// adds half its base weight on top
+    pub fn type_weights(&self, droughts: &GemDroughts) -> Vec<(GemType, u32)> {
+        self.types
+            .iter()
+            .map(|&(typ, weight)| {
+                let missed = if self.bad_luck_protection {
+                    droughts.get(&typ).copied().unwrap_or_default()
+                } else {
+                    0
+                };
+                (
+                    typ,
+                    weight.saturating_add(weight.saturating_mul(missed) / 2),
+                )
+            })
+            .collect()
+    }
+
+    pub fn quality_weights(&self, level: u32) -> &[(GemQuality, u32)] {
+        let level = (level as usize).min(self.qualities.len().saturating_sub(1));
+        self.qualities.get(level).map_or(&[], Vec::as_slice)
+    }
+
+    pub fn roll_type(&self, droughts: &mut GemDroughts, rng: &mut GameRng) -> GemType {
+        let weights = self.type_weights(droughts);
+        // Even odds roll the way they always have so existing seeds keep giving the same gems
+        let typ = if weights.iter().all(|(_, weight)| *weight == weights[0].1) {
+            weights[rng.u8(0..weights.len() as u8) as usize].0
+        } else {
+            Self::pick(&weights, rng.u32(0..Self::total(&weights)))
+        };
+        for &(other, _) in &self.types {
+            let missed = droughts.entry(other).or_default();
+            *missed = if other == typ { 0 } else { *missed + 1 };
+        }
+        typ
+    }
+
+    #[allow(clippy::cast_sign_loss)]
+    pub fn roll_quality(&self, level: u32, rng: &mut GameRng) -> GemQuality {
+        let weights = self.quality_weights(level);
+        if let [(quality, _)] = weights {
+            return *quality;
+        }
+        Self::pick(weights, (rng.f32() * Self::total(weights) as f32) as u32)
+    }
+
+    /// The summed weight of a table, capped so huge configured weights can't overflow
+    pub fn total<T>(weights: &[(T, u32)]) -> u32 {
+        weights
+            .iter()
+            .fold(0, |total, (_, weight)| total.saturating_add(*weight))
+    }
+
+    /// The entry a roll below the total weight lands on
+    fn pick<T: Copy>(weights: &[(T, u32)], mut roll: u32) -> T {
+        for (value, weight) in weights {
+            if roll < *weight {
+                return *value;
+            }
+            roll -= weight;
         }
+        weights[weights.len() - 1].0
     }
 }
+
+/// How many gems have been built since each gem type last came up, for the bad luck protection
+#[derive(Resource, Default, Deref, DerefMut)]
+pub struct GemDroughts(pub HashMap<GemType, u32>);
+
 impl From<GemType> for StandardMaterial {
     fn from(val: GemType) -> Self {
         let mut color: StandardMaterial = Into::<Color>::into(val).into();
@@ -200,28 +380,12 @@ impl From<GemType> for Color {
 }
 
 impl GemType {
-    pub fn random() -> Self {
-        match fastrand::u8(0..3) {
-            0 => GemType::Emerald,
-            1 => GemType::Aquamarine,
-            2 => GemType::Opal,
-            _ => panic!("Gem type larger than 6, this cannot happen"),
-        }
-        // match fastrand::u8(0..8) {
-        //     0 => GemType::Emerald,
-        //     1 => GemType::Ruby,
-        //     2 => GemType::Sapphire,
-        //     3 => GemType::Diamond,
-        //     4 => GemType::Amethyst,
-        //     5 => GemType::Opal,
-        //     6 => GemType::Aquamarine,
-        //     7 => GemType::Topaz,
-        //     _ => panic!("Gem type larger than 6, this cannot happen"),
-        // }
-    }
+    /// Gem types that can come out of building a tower, Ruby, Sapphire, Diamond, Amethyst and
+    /// Topaz are left out for now
+    pub const CANDIDATES: [GemType; 3] = [GemType::Emerald, GemType::Aquamarine, GemType::Opal];
 }
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq, Reflect, FromReflect)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Reflect, FromReflect, Serialize, Deserialize)]
 pub enum SpecialTowerType {
     Malachite(u32),
 }
@@ -235,25 +399,31 @@ pub struct SpecialTowerRecipe {
 pub struct UpdateFulfillableSpecialTowerRecipes;
 
 impl UpdateFulfillableSpecialTowerRecipes {
-    pub fn fire(mut events: EventWriter<UpdateFulfillableSpecialTowerRecipes>) {
-        events.send(Self);
+    /// Any tower being built, uncovered, picked, combined or removed can change what is
+    /// fulfillable
+    pub fn fire_on_change(
+        mut events: EventWriter<UpdateFulfillableSpecialTowerRecipes>,
+        changed_towers: Query<(), Changed<Tower>>,
+        mut removed_towers: RemovedComponents<Tower>,
+    ) {
+        if removed_towers.iter().count() > 0 || !changed_towers.is_empty() {
+            events.send(Self);
+        }
     }
 
+    /// Recipes can be made out of every tower on the map, whether it was placed in an earlier
+    /// round or is one of this round's gems
     pub fn run(
         mut events: EventReader<UpdateFulfillableSpecialTowerRecipes>,
         mut fulfillable: ResMut<FulfillableSpecialTowerRecipes>,
         recipes: Res<SpecialTowerRecipes>,
-        phase: Res<State<Phase>>,
-        towers: Query<&Tower, Without<JustBuilt>>,
-        just_built_towers: Query<&Tower, With<JustBuilt>>,
+        towers: Query<&Tower>,
     ) {
-        for _ in events.iter() {
-            **fulfillable = dbg!(recipes.get_fulfilled_recipes(match phase.0 {
-                Phase::Pick => just_built_towers.iter().collect(),
-                Phase::Spawn => towers.iter().collect(),
-                Phase::Build => unimplemented!(),
-            }));
+        if events.is_empty() {
+            return;
         }
+        events.clear();
+        **fulfillable = recipes.get_fulfilled_recipes(towers.iter().collect());
     }
 }
 
@@ -304,7 +474,36 @@ impl SpecialTowerRecipes {
     }
 }
 
-#[derive(Component, Clone, Copy, Debug, PartialEq, Eq, Reflect, FromReflect)]
+/// Something the player asked for that the game can't do, shown to them instead of crashing
+#[derive(Clone, Debug, PartialEq)]
+pub enum GameError {
+    CannotRefine(Tower),
+    CannotAttack(Tower),
+    NoRecipe(Tower),
+    NothingToMerge(Tower),
+    MissingTower(Entity),
+}
+
+impl Display for GameError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            GameError::CannotRefine(Tower::Gem { .. }) => {
+                write!(f, "Perfect gems can't be refined")
+            }
+            GameError::CannotRefine(tower) => write!(f, "{tower} can't be refined"),
+            GameError::CannotAttack(tower) => write!(f, "{tower} can't attack"),
+            GameError::NoRecipe(tower) => write!(f, "No special tower can be made with {tower}"),
+            GameError::NothingToMerge(tower) => {
+                write!(f, "No other {tower} was built this round to merge with")
+            }
+            GameError::MissingTower(entity) => write!(f, "Tower {entity:?} no longer exists"),
+        }
+    }
+}
+
+#[derive(
+    Component, Clone, Copy, Debug, PartialEq, Eq, Reflect, FromReflect, Serialize, Deserialize,
+)]
 pub enum Tower {
     Gem { typ: GemType, quality: GemQuality },
     Special(SpecialTowerType),
@@ -359,36 +558,16 @@ impl Tower {
                     multiplier: 0.5,
                     range: 3.,
                 }),
-                (GemType::Opal, GemQuality::Chipped) => entity.insert(Aura {
-                    typ: AuraType::Opal(10),
-                    range: 8.,
-                }),
-                (GemType::Opal, GemQuality::Flawed) => entity.insert(Aura {
-                    typ: AuraType::Opal(15),
-                    range: 9.,
-                }),
-                (GemType::Opal, GemQuality::Normal) => entity.insert(Aura {
-                    typ: AuraType::Opal(20),
-                    range: 10.,
-                }),
-                (GemType::Opal, GemQuality::Flawless) => entity.insert(Aura {
-                    typ: AuraType::Opal(25),
-                    range: 11.,
-                }),
-                (GemType::Opal, GemQuality::Perfect) => entity.insert(Aura {
-                    typ: AuraType::Opal(35),
-                    range: 12.,
-                }),
+                (GemType::Opal, quality) => entity.insert(Aura::opal(quality)),
                 _ => entity,
             },
-            Tower::Dirt => unimplemented!(),
-            Tower::Special(_) => entity,
+            Tower::Dirt | Tower::Special(_) => entity,
         }
         .id()
     }
 
-    pub fn get_base_cooldown_time(self) -> f32 {
-        match self {
+    pub fn get_base_cooldown_time(self) -> Result<f32, GameError> {
+        Ok(match self {
             Tower::Gem { typ, quality } => match (typ, quality) {
                 (GemType::Aquamarine, _) => BASE_TOWER_SPEED / 2.,
                 (
@@ -403,28 +582,73 @@ impl Tower {
                 ) => BASE_TOWER_SPEED - 0.2,
                 _ => BASE_TOWER_SPEED,
             },
-            Tower::Dirt => unimplemented!(),
+            Tower::Dirt => return Err(GameError::CannotAttack(self)),
             Tower::Special(_) => BASE_TOWER_SPEED,
-        }
+        })
     }
 
-    pub fn get_refine(self) -> Self {
+    /// The gem one quality up, perfect gems, dirt and special towers can't be refined
+    pub fn get_refine(self) -> Result<Self, GameError> {
         match self {
-            Tower::Gem { typ, quality } => Self::Gem {
+            Tower::Gem { typ, quality } => Ok(Self::Gem {
                 typ,
                 quality: match quality {
                     GemQuality::Chipped => GemQuality::Flawed,
                     GemQuality::Flawed => GemQuality::Normal,
                     GemQuality::Normal => GemQuality::Flawless,
                     GemQuality::Flawless => GemQuality::Perfect,
-                    GemQuality::Perfect => unimplemented!("Cannot refine perfect gems"),
+                    GemQuality::Perfect => return Err(GameError::CannotRefine(self)),
                 },
-            },
-            Tower::Dirt => unimplemented!("Cannot refine dirt"),
-            Tower::Special(_) => unimplemented!("Cannot refine special towers"),
+            }),
+            Tower::Dirt | Tower::Special(_) => Err(GameError::CannotRefine(self)),
         }
     }
 
+    /// Spawns a fully equipped tower of this type on the given ground position
+    pub fn spawn<'w, 's, 'a>(
+        self,
+        commands: &'a mut Commands<'w, 's>,
+        meshes: &mut Assets<Mesh>,
+        mats: &mut Assets<StandardMaterial>,
+        position: Vec2,
+    ) -> EntityCommands<'w, 's, 'a> {
+        let transform = Transform::from_xyz(position.x, self.get_y_offset(), position.y);
+        let (Ok(attack), Ok(cooldown)) = (LaserAttack::try_from(self), Cooldown::try_from(self))
+        else {
+            // Dirt is the only tower without an attack
+            return commands.spawn((
+                PbrBundle {
+                    mesh: meshes.add(self.into()),
+                    material: mats.add(Color::ORANGE_RED.into()),
+                    transform,
+                    ..default()
+                },
+                Name::new("Dirt"),
+                self,
+            ));
+        };
+        let material = match self {
+            Tower::Gem { typ, .. } => typ.into(),
+            Tower::Special(_) | Tower::Dirt => Color::BLACK.into(),
+        };
+        let mut entity = commands.spawn((
+            PbrBundle {
+                mesh: meshes.add(self.into()),
+                material: mats.add(material),
+                transform,
+                ..default()
+            },
+            Name::new(self.to_string()),
+            self,
+            attack,
+            cooldown,
+            Target::Single(None),
+            SpeedModifiers::default(),
+        ));
+        self.add_abilities(&mut entity);
+        entity
+    }
+
     pub fn get_y_offset(self) -> f32 {
         match self {
             Tower::Gem { quality, .. } => match quality {
@@ -478,10 +702,12 @@ impl Display for Tower {
     }
 }
 
-impl From<Tower> for LaserAttack {
+impl TryFrom<Tower> for LaserAttack {
+    type Error = GameError;
+
     #[allow(clippy::match_same_arms)]
-    fn from(value: Tower) -> Self {
-        match value {
+    fn try_from(value: Tower) -> Result<Self, GameError> {
+        Ok(match value {
             Tower::Gem { typ, quality } => Self {
                 range: match (typ, quality) {
                     (GemType::Emerald, GemQuality::Chipped) => 5.,
@@ -566,7 +792,7 @@ impl From<Tower> for LaserAttack {
                     _ => Hits::All,
                 },
             },
-            Tower::Dirt => unimplemented!(),
+            Tower::Dirt => return Err(GameError::CannotAttack(value)),
             Tower::Special(typ) => match typ {
                 SpecialTowerType::Malachite(_) => Self {
                     range: 7.5,
@@ -575,18 +801,20 @@ impl From<Tower> for LaserAttack {
                     hits: Hits::All,
                 },
             },
-        }
+        })
     }
 }
 
-impl From<Tower> for Cooldown {
-    fn from(value: Tower) -> Self {
+impl TryFrom<Tower> for Cooldown {
+    type Error = GameError;
+
+    fn try_from(value: Tower) -> Result<Self, GameError> {
         // Make a cooldown timer that starts in a finished state
-        let time = value.get_base_cooldown_time();
+        let time = value.get_base_cooldown_time()?;
         let mut timer = Timer::from_seconds(time, TimerMode::Once);
         timer.tick(Duration::from_secs_f32(time));
 
-        Cooldown(timer)
+        Ok(Cooldown(timer))
     }
 }
 
@@ -649,6 +877,7 @@ pub struct LaserAttack {
 impl LaserAttack {
     pub fn attack(
         mut lines: ResMut<DebugLines>,
+        mut rng: ResMut<GameRng>,
         time: Res<Time>,
         mut writer: EventWriter<Hit>,
         mut towers: Query<
@@ -707,7 +936,7 @@ impl LaserAttack {
                             writer.send(Hit {
                                 source: tower,
                                 target: *target_entity,
-                                value: attack.damage.clone().get_value(),
+                                value: attack.damage.clone().get_value(&mut rng),
                             });
                         }
                     } else {
@@ -804,9 +1033,9 @@ pub enum Damage {
 }
 
 impl Damage {
-    pub fn get_value(self) -> u32 {
+    pub fn get_value(self, rng: &mut GameRng) -> u32 {
         match self {
-            Damage::Range(range) => fastrand::u32(range),
+            Damage::Range(range) => rng.u32(range),
             Damage::Fixed(val) => val,
         }
     }
@@ -923,6 +1152,7 @@ impl RefineAndPickSelectedTower {
         mut commands: Commands,
         mut refine_events: EventReader<RefineAndPickSelectedTower>,
         mut pick_events: EventWriter<PickSelectedTower>,
+        mut errors: EventWriter<GameError>,
         mut meshes: ResMut<Assets<Mesh>>,
         mut mats: ResMut<Assets<StandardMaterial>>,
         selected: Option<Res<SelectedTower>>,
@@ -937,44 +1167,119 @@ impl RefineAndPickSelectedTower {
                     continue;
                 }
                 if let Ok((old_tower, tower_pos, tower)) = towers.get(*tower) {
-                    commands.entity(old_tower).despawn_recursive();
-                    match tower.get_refine() {
-                        Tower::Gem { typ, quality } => {
-                            let new_tower = Tower::Gem { typ, quality };
-                            let cooldown: Cooldown = new_tower.into();
-                            let new_tower = new_tower.add_abilities(&mut commands.spawn((
-                                PbrBundle {
-                                    mesh: meshes.add(new_tower.into()),
-                                    material: mats.add(typ.into()),
-                                    transform: Transform::from_xyz(
-                                        tower_pos.compute_transform().translation.x,
-                                        new_tower.get_y_offset(),
-                                        tower_pos.compute_transform().translation.z,
-                                    ),
-                                    ..default()
-                                },
-                                Name::new(new_tower.to_string()),
-                                new_tower,
-                                LaserAttack::from(new_tower),
-                                cooldown,
-                                Target::Single(None),
-                                SpeedModifiers::default(),
-                                JustBuilt,
-                            )));
-                            commands.insert_resource(SelectedTower {
-                                tower: new_tower,
-                                pickable: true,
-                                refinable: false,
-                                removable: false,
-                                combinable: false,
-                            });
-                            pick_events.send(PickSelectedTower);
+                    let new_tower = match tower.get_refine() {
+                        Ok(new_tower) => new_tower,
+                        Err(err) => {
+                            errors.send(err);
+                            continue;
                         }
-                        Tower::Dirt => println!("Can't refine and pick dirt"),
-                        Tower::Special(_) => println!("Can't refine and pick special tower"),
-                    }
+                    };
+                    commands.entity(old_tower).despawn_recursive();
+                    let new_tower = new_tower
+                        .spawn(
+                            &mut commands,
+                            &mut meshes,
+                            &mut mats,
+                            tower_pos.translation().xz(),
+                        )
+                        .insert(JustBuilt)
+                        .id();
+                    commands.insert_resource(SelectedTower {
+                        tower: new_tower,
+                        pickable: true,
+                        refinable: false,
+                        removable: false,
+                        combinable: false,
+                        mergeable: false,
+                    });
+                    pick_events.send(PickSelectedTower);
+                }
+            }
+        }
+    }
+}
+
+/// Merges the selected gem with identical gems built this round and picks the result, two gems
+/// make one of the next quality and four go up two qualities
+#[derive(Default)]
+pub struct MergeSelectedTower;
+
+impl MergeSelectedTower {
+    pub fn merge(
+        mut commands: Commands,
+        mut events: EventReader<MergeSelectedTower>,
+        mut pick_events: EventWriter<PickSelectedTower>,
+        mut errors: EventWriter<GameError>,
+        mut meshes: ResMut<Assets<Mesh>>,
+        mut mats: ResMut<Assets<StandardMaterial>>,
+        selected: Option<Res<SelectedTower>>,
+        just_built: Query<(Entity, &GlobalTransform, &Tower), With<JustBuilt>>,
+    ) {
+        for _ in events.iter() {
+            let Some(SelectedTower {
+                tower: selected,
+                mergeable,
+                ..
+            }) = selected.as_deref()
+            else {
+                continue;
+            };
+            if !mergeable {
+                continue;
+            }
+            let Ok((_, position, &tower)) = just_built.get(*selected) else {
+                errors.send(GameError::MissingTower(*selected));
+                continue;
+            };
+            let duplicates = just_built
+                .iter()
+                .filter(|(entity, _, other)| entity != selected && **other == tower)
+                .collect::<Vec<_>>();
+            if duplicates.is_empty() {
+                errors.send(GameError::NothingToMerge(tower));
+                continue;
+            }
+            let once = match tower.get_refine() {
+                Ok(once) => once,
+                Err(err) => {
+                    errors.send(err);
+                    continue;
                 }
+            };
+            // Four of a kind only skip a quality if there is one left to skip to
+            let (merged, used) = match (duplicates.len(), once.get_refine()) {
+                (3.., Ok(twice)) => (twice, 3),
+                _ => (once, 1),
+            };
+
+            commands.entity(*selected).despawn_recursive();
+            for (entity, transform, _) in &duplicates[..used] {
+                commands.entity(*entity).despawn_recursive();
+                Tower::Dirt.spawn(
+                    &mut commands,
+                    &mut meshes,
+                    &mut mats,
+                    transform.translation().xz(),
+                );
             }
+            let merged = merged
+                .spawn(
+                    &mut commands,
+                    &mut meshes,
+                    &mut mats,
+                    position.translation().xz(),
+                )
+                .insert(JustBuilt)
+                .id();
+            commands.insert_resource(SelectedTower {
+                tower: merged,
+                pickable: true,
+                refinable: false,
+                removable: false,
+                combinable: false,
+                mergeable: false,
+            });
+            pick_events.send(PickSelectedTower);
         }
     }
 }
@@ -982,114 +1287,253 @@ impl RefineAndPickSelectedTower {
 #[derive(Default, Deref, DerefMut, Resource)]
 pub struct RandomLevel(u32);
 
+#[derive(Default)]
+pub struct IncreaseUpgradeChance;
+
+impl IncreaseUpgradeChance {
+    pub fn increase(
+        mut events: EventReader<IncreaseUpgradeChance>,
+        mut random_level: ResMut<RandomLevel>,
+    ) {
+        for _ in events.iter() {
+            **random_level += 1;
+        }
+    }
+}
+
 #[derive(Default)]
 pub struct CombineSelectedTower;
 
+/// A special tower was made out of its recipe
+pub struct SpecialTowerCombined {
+    pub tower: Entity,
+    pub typ: SpecialTowerType,
+}
+
+/// One way the selected tower can be combined: a recipe and the exact towers it would use up
+#[derive(Clone, Debug, PartialEq)]
+pub struct CombineOption {
+    pub recipe: SpecialTowerRecipe,
+    /// Includes the selected tower
+    pub ingredients: Vec<Entity>,
+}
+
+/// Every way the selected tower can be combined right now, the player steps through them with
+/// [`CycleCombineOption`] when there is more than one recipe or more than one tower of a kind
+#[derive(Resource, Default)]
+pub struct CombineOptions {
+    pub options: Vec<CombineOption>,
+    pub chosen: usize,
+}
+
+impl CombineOptions {
+    /// Stops duplicate towers from turning into a combinatorial explosion of options
+    const MAX_OPTIONS: usize = 16;
+
+    pub fn chosen(&self) -> Option<&CombineOption> {
+        self.options.get(self.chosen)
+    }
+
+    pub fn update(
+        mut options: ResMut<CombineOptions>,
+        selected: Option<ResMut<SelectedTower>>,
+        fulfillable: Res<FulfillableSpecialTowerRecipes>,
+        towers: Query<(Entity, &Tower)>,
+        changed_towers: Query<(), Changed<Tower>>,
+        mut removed_towers: RemovedComponents<Tower>,
+    ) {
+        let towers_changed = removed_towers.iter().count() > 0 || !changed_towers.is_empty();
+        let Some(mut selected) = selected else {
+            if !options.options.is_empty() {
+                *options = Self::default();
+            }
+            return;
+        };
+        if !towers_changed && !selected.is_changed() && !fulfillable.is_changed() {
+            return;
+        }
+        let pool = towers
+            .iter()
+            .map(|(entity, tower)| (entity, *tower))
+            .collect::<Vec<_>>();
+        let new_options = pool
+            .iter()
+            .find(|(entity, _)| *entity == selected.tower)
+            .map(|&(_, selected_type)| {
+                Self::find(&fulfillable, selected.tower, selected_type, &pool)
+            })
+            .unwrap_or_default();
+        // Keep the player's choice while it is still there
+        let chosen = options
+            .chosen()
+            .and_then(|chosen| new_options.iter().position(|option| option == chosen))
+            .unwrap_or_default();
+        let combinable = !new_options.is_empty();
+        if selected.combinable != combinable {
+            selected.combinable = combinable;
+        }
+        *options = Self {
+            options: new_options,
+            chosen,
+        };
+    }
+
+    fn find(
+        recipes: &[SpecialTowerRecipe],
+        selected: Entity,
+        selected_type: Tower,
+        pool: &[(Entity, Tower)],
+    ) -> Vec<CombineOption> {
+        let mut options = vec![];
+        for recipe in recipes {
+            let Some(index) = recipe
+                .ingredients
+                .iter()
+                .position(|tower| *tower == selected_type)
+            else {
+                continue;
+            };
+            let mut needed = recipe.ingredients.clone();
+            needed.remove(index);
+            let mut sets = vec![];
+            Self::assign(&needed, pool, &mut vec![selected], &mut sets);
+            for ingredients in sets {
+                if options.len() == Self::MAX_OPTIONS {
+                    return options;
+                }
+                options.push(CombineOption {
+                    recipe: recipe.clone(),
+                    ingredients,
+                });
+            }
+        }
+        options
+    }
+
+    /// Every distinct set of towers from the pool covering the needed ingredients
+    fn assign(
+        needed: &[Tower],
+        pool: &[(Entity, Tower)],
+        used: &mut Vec<Entity>,
+        sets: &mut Vec<Vec<Entity>>,
+    ) {
+        let Some((ingredient, rest)) = needed.split_first() else {
+            let mut set = used.clone();
+            set[1..].sort();
+            if !sets.contains(&set) {
+                sets.push(set);
+            }
+            return;
+        };
+        for (entity, tower) in pool {
+            if tower != ingredient || used.contains(entity) || sets.len() >= Self::MAX_OPTIONS {
+                continue;
+            }
+            used.push(*entity);
+            Self::assign(rest, pool, used, sets);
+            used.pop();
+        }
+    }
+}
+
+/// Moves on to the next of the [`CombineOptions`]
+#[derive(Default)]
+pub struct CycleCombineOption;
+
+impl CycleCombineOption {
+    pub fn cycle(mut events: EventReader<CycleCombineOption>, mut options: ResMut<CombineOptions>) {
+        for _ in events.iter() {
+            if !options.options.is_empty() {
+                options.chosen = (options.chosen + 1) % options.options.len();
+            }
+        }
+    }
+}
+
 impl CombineSelectedTower {
     pub fn run(
         mut commands: Commands,
         mut pick_events: EventWriter<PickSelectedTower>,
         mut events: EventReader<CombineSelectedTower>,
+        mut combined: EventWriter<SpecialTowerCombined>,
+        mut errors: EventWriter<GameError>,
         mut meshes: ResMut<Assets<Mesh>>,
         mut mats: ResMut<Assets<StandardMaterial>>,
         phase: Res<State<Phase>>,
-        fulfillable_recipes: Res<FulfillableSpecialTowerRecipes>,
+        options: Res<CombineOptions>,
         selected_tower: Option<Res<SelectedTower>>,
-        towers: Query<(Entity, &Tower)>,
-        transforms: Query<&GlobalTransform>,
+        towers: Query<(&Tower, &GlobalTransform)>,
+        just_built: Query<(), With<JustBuilt>>,
     ) {
         for _ in events.iter() {
-            if let Some(SelectedTower {
+            let Some(SelectedTower {
                 tower: selected_tower,
                 combinable,
                 ..
             }) = selected_tower.as_deref()
-            {
-                if !combinable {
-                    continue;
+            else {
+                continue;
+            };
+            if !combinable {
+                continue;
+            }
+            let Ok((&target_type, position)) = towers.get(*selected_tower) else {
+                errors.send(GameError::MissingTower(*selected_tower));
+                continue;
+            };
+            let Some(CombineOption {
+                recipe,
+                ingredients,
+            }) = options.chosen()
+            else {
+                errors.send(GameError::NoRecipe(target_type));
+                continue;
+            };
+            if let Some(missing) = ingredients.iter().find(|entity| !towers.contains(**entity)) {
+                errors.send(GameError::MissingTower(*missing));
+                continue;
+            }
+            for (entity, (_, transform)) in ingredients.iter().zip(towers.iter_many(ingredients)) {
+                commands.entity(*entity).despawn_recursive();
+                if entity != selected_tower {
+                    Tower::Dirt.spawn(
+                        &mut commands,
+                        &mut meshes,
+                        &mut mats,
+                        transform.translation().xz(),
+                    );
                 }
-                let target_type = towers.get_component::<Tower>(*selected_tower).unwrap();
-                let recipe = fulfillable_recipes
-                    .iter()
-                    .find(|recipe| recipe.ingredients.contains(target_type))
-                    .unwrap();
-                let mut needed_ingredients = recipe
-                    .ingredients
-                    .iter()
-                    .filter(|tower| tower != &target_type)
-                    .collect::<Vec<_>>();
-                dbg!(&needed_ingredients);
-                let mut ingredients = towers
+            }
+            // Using up one of this round's gems makes the special tower the pick, otherwise it is
+            // made out of towers that were already placed and stays placed
+            let from_this_round = phase.0 == Phase::Pick
+                && ingredients
                     .iter()
-                    .filter(|(_, tower)| {
-                        let is_ingredient = needed_ingredients.contains(tower);
-                        if is_ingredient {
-                            needed_ingredients = needed_ingredients
-                                .iter()
-                                .filter_map(|typ| if typ == tower { None } else { Some(*typ) })
-                                .collect();
-                        }
-                        is_ingredient
-                    })
-                    .collect::<Vec<_>>();
-                ingredients.push((*selected_tower, target_type));
-                if dbg!(needed_ingredients).is_empty()
-                    && ingredients.len() == recipe.ingredients.len()
-                {
-                    for (entity, _) in dbg!(ingredients) {
-                        let transform = transforms.get(entity).unwrap();
-                        commands.entity(entity).despawn_recursive();
-                        if &entity != selected_tower {
-                            commands.spawn((
-                                PbrBundle {
-                                    mesh: meshes.add(Tower::Dirt.into()),
-                                    material: mats.add(Color::ORANGE_RED.into()),
-                                    transform: Transform::from_xyz(
-                                        transform.compute_transform().translation.x,
-                                        Tower::Dirt.get_y_offset(),
-                                        transform.compute_transform().translation.z,
-                                    ),
-                                    ..default()
-                                },
-                                Name::new("Dirt"),
-                                Tower::Dirt,
-                            ));
-                        }
-                    }
-                    let position = transforms.get(*selected_tower).unwrap();
-                    let new_tower = Tower::Special(recipe.typ);
-                    let cooldown: Cooldown = new_tower.into();
-                    let new_tower = new_tower.add_abilities(&mut commands.spawn((
-                        PbrBundle {
-                            mesh: meshes.add(new_tower.into()),
-                            material: mats.add(Color::BLACK.into()),
-                            transform: Transform::from_xyz(
-                                position.compute_transform().translation.x,
-                                new_tower.get_y_offset(),
-                                position.compute_transform().translation.z,
-                            ),
-                            ..default()
-                        },
-                        Name::new(new_tower.to_string()),
-                        new_tower,
-                        LaserAttack::from(new_tower),
-                        cooldown,
-                        Target::Single(None),
-                        SpeedModifiers::default(),
-                        JustBuilt,
-                    )));
-                    let in_picking_phase = phase.0 == Phase::Pick;
-                    commands.insert_resource(SelectedTower {
-                        tower: new_tower,
-                        pickable: in_picking_phase,
-                        refinable: false,
-                        removable: false,
-                        combinable: false,
-                    });
-                    if in_picking_phase {
-                        pick_events.send(PickSelectedTower);
-                    }
-                }
+                    .any(|entity| just_built.contains(*entity));
+            let mut new_tower = Tower::Special(recipe.typ).spawn(
+                &mut commands,
+                &mut meshes,
+                &mut mats,
+                position.translation().xz(),
+            );
+            if from_this_round {
+                new_tower.insert(JustBuilt);
+            }
+            let new_tower = new_tower.id();
+            combined.send(SpecialTowerCombined {
+                tower: new_tower,
+                typ: recipe.typ,
+            });
+            commands.insert_resource(SelectedTower {
+                tower: new_tower,
+                pickable: from_this_round,
+                refinable: false,
+                removable: false,
+                combinable: false,
+                mergeable: false,
+            });
+            if from_this_round {
+                pick_events.send(PickSelectedTower);
             }
         }
     }
@@ -0,0 +1,91 @@
+mod common;
+
+use std::time::Duration;
+
+use bevy::prelude::*;
+
+use common::TestGame;
+use gem_td::{
+    creeps::{DamageKind, Slow, SlowSource},
+    tower_abilities::{SpeedModifierType, SpeedModifiers},
+    towers::{GemQuality, GemType, Tower},
+};
+
+fn gem(typ: GemType, quality: GemQuality) -> Tower {
+    Tower::Gem { typ, quality }
+}
+
+#[test]
+fn splash_hits_creeps_around_the_target() {
+    let mut game = TestGame::new();
+    game.place(gem(GemType::Ruby, GemQuality::Chipped), UVec2::new(2, 2));
+    game.start_wave();
+    let target = game.spawn_creep(Vec2::new(2., 4.), 100);
+    let nearby = game.spawn_creep(Vec2::new(2., 6.), 100);
+    let outside = game.spawn_creep(Vec2::new(2., 9.), 100);
+
+    game.advance(Duration::from_millis(100));
+
+    let hits = game.dealt(target, DamageKind::Attack);
+    assert_eq!(hits.len(), 1);
+    assert_eq!(game.dealt(nearby, DamageKind::Splash), [hits[0] / 2]);
+    assert_eq!(game.hitpoints(nearby), Some(100 - hits[0] / 2));
+    assert!(game.dealt(target, DamageKind::Splash).is_empty());
+    assert_eq!(game.hitpoints(outside), Some(100));
+}
+
+#[test]
+fn slow_poison_slows_and_ticks_until_it_runs_out() {
+    let mut game = TestGame::new();
+    let tower = game.place(gem(GemType::Emerald, GemQuality::Chipped), UVec2::new(2, 2));
+    game.start_wave();
+    let creep = game.spawn_creep(Vec2::new(2., 4.), 1_000);
+
+    game.advance(Duration::from_millis(100));
+    assert_eq!(
+        game.get::<Slow>(creep).unwrap().get(&SlowSource::Poison),
+        Some(&15)
+    );
+
+    // Stop the tower from refreshing the poison and let it wear off
+    game.remove(tower);
+    game.advance(Duration::from_secs(4));
+
+    let ticks = game.dealt(creep, DamageKind::Poison);
+    assert!(!ticks.is_empty());
+    assert!(ticks.iter().all(|tick| *tick == 2));
+    assert_eq!(
+        game.get::<Slow>(creep).unwrap().get(&SlowSource::Poison),
+        None
+    );
+    let ticks = ticks.len();
+    game.advance(Duration::from_secs(2));
+    assert_eq!(game.dealt(creep, DamageKind::Poison).len(), ticks);
+}
+
+#[test]
+fn strongest_aura_in_range_applies() {
+    let opal_modifier = |game: &TestGame, tower| -> Option<u32> {
+        game.get::<SpeedModifiers>(tower)
+            .unwrap()
+            .get(&SpeedModifierType::OpalAura)
+            .copied()
+    };
+    let mut game = TestGame::new();
+    game.place(gem(GemType::Opal, GemQuality::Chipped), UVec2::new(2, 2));
+    let near = game.place(gem(GemType::Emerald, GemQuality::Chipped), UVec2::new(4, 2));
+    let far = game.place(
+        gem(GemType::Emerald, GemQuality::Chipped),
+        UVec2::new(14, 14),
+    );
+    assert_eq!(opal_modifier(&game, near), Some(10));
+    assert_eq!(opal_modifier(&game, far), None);
+
+    let perfect = game.place(gem(GemType::Opal, GemQuality::Perfect), UVec2::new(4, 4));
+    assert_eq!(opal_modifier(&game, near), Some(35));
+    assert_eq!(opal_modifier(&game, far), None);
+
+    game.remove(perfect);
+    game.step();
+    assert_eq!(opal_modifier(&game, near), Some(10));
+}
@@ -0,0 +1,184 @@
+//! Runs the gameplay without rendering so tests can set up towers and creeps, advance time and
+//! check what happened
+
+// Every test binary only uses part of the harness
+#![allow(dead_code)]
+
+use std::time::Duration;
+
+use bevy::{ecs::system::SystemState, prelude::*};
+
+use gem_td::{
+    common::CreepPos,
+    controls::GameCommand,
+    creeps::{
+        Creep, CreepSpawner, CreepType, DamageDealt, DamageKind, DamageSet, Dead, HitPoints, Slow,
+    },
+    headless::{self, TIMESTEP},
+    towers::{BuildGrid, JustBuilt, Tower},
+    Phase,
+};
+
+const SEED: u64 = 1;
+
+/// Everything that happened to creeps since the game started
+#[derive(Resource, Default)]
+struct Recorded {
+    dealt: Vec<DamageDealt>,
+    deaths: Vec<Dead>,
+}
+
+impl Recorded {
+    fn record(
+        mut recorded: ResMut<Recorded>,
+        mut dealt: EventReader<DamageDealt>,
+        mut deaths: EventReader<Dead>,
+    ) {
+        recorded.dealt.extend(dealt.iter().cloned());
+        recorded.deaths.extend(deaths.iter().cloned());
+    }
+}
+
+pub struct TestGame {
+    pub app: App,
+}
+
+impl TestGame {
+    pub fn new() -> Self {
+        let mut app = headless::app(SEED);
+        app.init_resource::<Recorded>()
+            .add_system(Recorded::record.in_set(DamageSet::Resolve));
+        // Run startup so the map exists
+        app.update();
+        Self { app }
+    }
+
+    /// Places a tower like the player would at the end of a pick
+    pub fn place(&mut self, tower: Tower, tile: UVec2) -> Entity {
+        let mut state: SystemState<(
+            Commands,
+            ResMut<Assets<Mesh>>,
+            ResMut<Assets<StandardMaterial>>,
+            ResMut<BuildGrid>,
+        )> = SystemState::new(&mut self.app.world);
+        let (mut commands, mut meshes, mut mats, mut build_grid) =
+            state.get_mut(&mut self.app.world);
+        assert!(build_grid.occupy(tile), "{tile} is already taken");
+        let tower = tower
+            .spawn(&mut commands, &mut meshes, &mut mats, tile.as_vec2())
+            .id();
+        state.apply(&mut self.app.world);
+        // Towers built during the game are only seen by other systems after their transform has
+        // been propagated
+        let transform = *self.app.world.get::<Transform>(tower).unwrap();
+        self.app
+            .world
+            .entity_mut(tower)
+            .insert(GlobalTransform::from(transform));
+        self.app.update();
+        tower
+    }
+
+    pub fn remove(&mut self, entity: Entity) {
+        self.app.world.entity_mut(entity).despawn_recursive();
+    }
+
+    /// Spawns a ground creep that stands still at a position on the map
+    pub fn spawn_creep(&mut self, position: Vec2, hitpoints: u32) -> Entity {
+        let transform = Transform::from_xyz(position.x, 0.25, position.y);
+        self.app
+            .world
+            .spawn((
+                TransformBundle {
+                    local: transform,
+                    global: transform.into(),
+                },
+                Creep {
+                    typ: CreepType::Ground,
+                },
+                HitPoints::new(hitpoints),
+                CreepPos { pos: position },
+                Slow::default(),
+            ))
+            .id()
+    }
+
+    /// Goes into the spawn phase without the spawner sending its own creeps, the wave ends once
+    /// the creeps spawned by the test are gone
+    pub fn start_wave(&mut self) {
+        self.app
+            .world
+            .resource_mut::<NextState<Phase>>()
+            .set(Phase::Spawn);
+        self.step();
+        let mut spawners = self.app.world.query::<&mut CreepSpawner>();
+        for mut spawner in spawners.iter_mut(&mut self.app.world) {
+            spawner.amount = 0;
+        }
+    }
+
+    /// Goes into the pick phase, gems placed with [`Self::place_just_built`] after this are the
+    /// ones to pick from
+    pub fn start_pick(&mut self) {
+        self.app
+            .world
+            .resource_mut::<NextState<Phase>>()
+            .set(Phase::Pick);
+        self.step();
+    }
+
+    /// Places a gem as one of this round's to pick from
+    pub fn place_just_built(&mut self, tower: Tower, tile: UVec2) -> Entity {
+        let tower = self.place(tower, tile);
+        self.app.world.entity_mut(tower).insert(JustBuilt);
+        tower
+    }
+
+    /// Does what the player would with a click or a hotkey, takes effect on the next step
+    pub fn command(&mut self, command: GameCommand) {
+        self.app.world.send_event(command);
+    }
+
+    pub fn step(&mut self) {
+        headless::step(&mut self.app);
+    }
+
+    pub fn advance(&mut self, duration: Duration) {
+        let mut elapsed = Duration::ZERO;
+        while elapsed < duration {
+            self.step();
+            elapsed += TIMESTEP;
+        }
+    }
+
+    pub fn hitpoints(&self, creep: Entity) -> Option<u32> {
+        self.app
+            .world
+            .get::<HitPoints>(creep)
+            .map(HitPoints::current)
+    }
+
+    pub fn get<T: Component>(&self, entity: Entity) -> Option<&T> {
+        self.app.world.get::<T>(entity)
+    }
+
+    pub fn phase(&self) -> Phase {
+        self.app.world.resource::<State<Phase>>().0.clone()
+    }
+
+    pub fn deaths(&self) -> &[Dead] {
+        &self.app.world.resource::<Recorded>().deaths
+    }
+
+    /// Damage of one kind that landed on a creep
+    pub fn dealt(&self, target: Entity, kind: DamageKind) -> Vec<u32> {
+        self.app
+            .world
+            .resource::<Recorded>()
+            .dealt
+            .iter()
+            .filter(|dealt| dealt.target == target && dealt.kind == kind)
+            .map(|dealt| dealt.amount)
+            .collect()
+    }
+}
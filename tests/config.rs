@@ -0,0 +1,13 @@
+use gem_td::config::Keymap;
+
+#[test]
+fn rebinding_to_a_taken_key_swaps_the_keys() {
+    let mut keymap = Keymap::default();
+    let (pick, refine_and_pick) = (keymap.pick, keymap.refine_and_pick);
+
+    // Pick is the first binding in the settings
+    keymap.rebind(0, refine_and_pick);
+
+    assert_eq!(keymap.pick, refine_and_pick);
+    assert_eq!(keymap.refine_and_pick, pick);
+}
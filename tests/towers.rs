@@ -0,0 +1,324 @@
+mod common;
+
+use std::time::Duration;
+
+use bevy::prelude::*;
+
+use common::TestGame;
+use gem_td::{
+    common::GameRng,
+    controls::GameCommand,
+    creeps::{DamageEvent, DamageKind},
+    towers::{
+        CombineOptions, GameError, GemChances, GemDroughts, GemQuality, GemType, JustBuilt, Tower,
+    },
+    CurrentLevel, Phase, MAP_WIDTH,
+};
+
+const EMERALD: Tower = Tower::Gem {
+    typ: GemType::Emerald,
+    quality: GemQuality::Chipped,
+};
+
+#[test]
+fn laser_attack_hits_creeps_in_range() {
+    let mut game = TestGame::new();
+    game.place(EMERALD, UVec2::new(2, 2));
+    let near = game.spawn_creep(Vec2::new(2., 4.), 100);
+    let far = game.spawn_creep(Vec2::new(12., 12.), 100);
+
+    game.advance(Duration::from_millis(100));
+
+    let hits = game.dealt(near, DamageKind::Attack);
+    assert_eq!(hits.len(), 1);
+    assert!((4..=7).contains(&hits[0]));
+    assert_eq!(game.hitpoints(near), Some(100 - hits[0]));
+    assert_eq!(game.hitpoints(far), Some(100));
+}
+
+#[test]
+fn laser_attack_waits_for_cooldown() {
+    let mut game = TestGame::new();
+    game.place(EMERALD, UVec2::new(2, 2));
+    let creep = game.spawn_creep(Vec2::new(2., 4.), 1_000);
+
+    game.advance(Duration::from_millis(500));
+    assert_eq!(game.dealt(creep, DamageKind::Attack).len(), 1);
+
+    game.advance(Duration::from_secs(1));
+    assert_eq!(game.dealt(creep, DamageKind::Attack).len(), 2);
+}
+
+#[test]
+fn killing_blow_sends_dead_and_despawns() {
+    let mut game = TestGame::new();
+    let tower = game.place(EMERALD, UVec2::new(2, 2));
+    let creep = game.spawn_creep(Vec2::new(2., 4.), 1);
+
+    game.advance(Duration::from_millis(100));
+
+    let [dead] = game.deaths() else {
+        panic!("expected exactly one death");
+    };
+    assert_eq!(dead.creep, creep);
+    assert_eq!(dead.killer, tower);
+    assert_eq!(dead.kind, DamageKind::Attack);
+    assert!(game.app.world.get_entity(creep).is_none());
+}
+
+#[test]
+fn wave_ends_when_all_creeps_are_dead() {
+    let mut game = TestGame::new();
+    game.place(EMERALD, UVec2::new(2, 2));
+    game.start_wave();
+    game.spawn_creep(Vec2::new(2., 4.), 1);
+    assert_eq!(game.phase(), Phase::Spawn);
+
+    game.advance(Duration::from_millis(100));
+
+    assert_eq!(game.phase(), Phase::Build);
+    assert_eq!(**game.app.world.resource::<CurrentLevel>(), 2);
+}
+
+#[test]
+fn creeps_reaching_the_goal_leak_and_end_the_wave() {
+    let mut game = TestGame::new();
+    game.start_wave();
+    let creep = game.spawn_creep(Vec2::new(MAP_WIDTH as f32 - 0.5, 0.5), 100);
+
+    game.advance(Duration::from_millis(100));
+
+    assert!(game.app.world.get_entity(creep).is_none());
+    assert!(game.deaths().is_empty());
+    assert_eq!(game.phase(), Phase::Build);
+}
+
+#[test]
+fn creeps_that_leak_are_not_also_killed() {
+    let mut game = TestGame::new();
+    let tower = game.place(EMERALD, UVec2::new(2, 2));
+    game.start_wave();
+    let creep = game.spawn_creep(Vec2::new(MAP_WIDTH as f32 - 0.5, 0.5), 1);
+    // Lands on the same frame the creep reaches the goal
+    game.app.world.send_event(DamageEvent {
+        source: tower,
+        target: creep,
+        amount: 1,
+        kind: DamageKind::Attack,
+    });
+
+    game.step();
+
+    assert!(game.app.world.get_entity(creep).is_none());
+    assert!(game.deaths().is_empty());
+    assert!(game.dealt(creep, DamageKind::Attack).is_empty());
+}
+
+#[test]
+fn refining_stops_at_perfect_gems() {
+    let flawless = Tower::Gem {
+        typ: GemType::Emerald,
+        quality: GemQuality::Flawless,
+    };
+    let perfect = Tower::Gem {
+        typ: GemType::Emerald,
+        quality: GemQuality::Perfect,
+    };
+
+    assert_eq!(flawless.get_refine(), Ok(perfect));
+    assert_eq!(perfect.get_refine(), Err(GameError::CannotRefine(perfect)));
+    assert_eq!(
+        Tower::Dirt.get_refine(),
+        Err(GameError::CannotRefine(Tower::Dirt))
+    );
+}
+
+#[test]
+fn combining_uses_the_chosen_duplicate_ingredient() {
+    let chipped = |typ| Tower::Gem {
+        typ,
+        quality: GemQuality::Chipped,
+    };
+    let mut game = TestGame::new();
+    let opal = game.place(chipped(GemType::Opal), UVec2::new(2, 2));
+    let emeralds = [
+        game.place(EMERALD, UVec2::new(2, 10)),
+        game.place(EMERALD, UVec2::new(10, 2)),
+    ];
+    game.place(chipped(GemType::Aquamarine), UVec2::new(10, 10));
+    game.start_wave();
+    // Keeps the wave going while the towers are combined
+    game.spawn_creep(Vec2::new(30., 30.), 1_000_000);
+    game.command(GameCommand::Select(Some(Vec2::new(2., 2.))));
+    game.advance(Duration::from_millis(100));
+
+    let chosen_emerald = |game: &TestGame| {
+        let options = game.app.world.resource::<CombineOptions>();
+        assert_eq!(options.options.len(), 2);
+        let ingredients = &options.chosen().unwrap().ingredients;
+        *emeralds
+            .iter()
+            .find(|emerald| ingredients.contains(emerald))
+            .unwrap()
+    };
+    let first_choice = chosen_emerald(&game);
+    game.command(GameCommand::CycleCombineOption);
+    game.step();
+    let used = chosen_emerald(&game);
+    assert_ne!(used, first_choice);
+
+    game.command(GameCommand::Combine);
+    game.step();
+
+    assert!(game.get::<Tower>(opal).is_none());
+    assert!(game.get::<Tower>(used).is_none());
+    assert_eq!(game.get::<Tower>(first_choice), Some(&EMERALD));
+}
+
+#[test]
+fn placed_towers_combine_during_the_build_phase() {
+    let chipped = |typ| Tower::Gem {
+        typ,
+        quality: GemQuality::Chipped,
+    };
+    let mut game = TestGame::new();
+    game.place(chipped(GemType::Opal), UVec2::new(2, 2));
+    game.place(EMERALD, UVec2::new(2, 10));
+    game.place(chipped(GemType::Aquamarine), UVec2::new(10, 10));
+    assert_eq!(game.phase(), Phase::Build);
+
+    game.command(GameCommand::Select(Some(Vec2::new(2., 2.))));
+    game.advance(Duration::from_millis(100));
+    game.command(GameCommand::Combine);
+    game.advance(Duration::from_millis(100));
+
+    let mut towers = game.app.world.query::<(&Tower, Option<&JustBuilt>)>();
+    let towers = towers.iter(&game.app.world).collect::<Vec<_>>();
+    assert_eq!(towers.len(), 3);
+    let [special] = towers
+        .iter()
+        .filter(|(tower, _)| matches!(tower, Tower::Special(_)))
+        .collect::<Vec<_>>()[..]
+    else {
+        panic!("expected exactly one special tower");
+    };
+    // Made out of placed towers so it is placed as well, not this round's pick
+    assert!(special.1.is_none());
+    assert_eq!(game.phase(), Phase::Build);
+}
+
+/// The gem merged at the selected tile and how many of this round's gems turned to dirt
+fn merge(game: &mut TestGame, gems: usize) -> (Option<Tower>, usize) {
+    game.start_pick();
+    for column in 0..gems as u32 {
+        game.place_just_built(EMERALD, UVec2::new(2 + 3 * column, 2));
+    }
+    game.command(GameCommand::Select(Some(Vec2::new(2., 2.))));
+    game.step();
+    game.command(GameCommand::Merge);
+    game.advance(Duration::from_millis(100));
+
+    let mut towers = game.app.world.query::<(&Tower, &GlobalTransform)>();
+    let merged = towers
+        .iter(&game.app.world)
+        .find(|(tower, transform)| **tower != Tower::Dirt && transform.translation().x == 2.)
+        .map(|(tower, _)| *tower);
+    let dirt = towers
+        .iter(&game.app.world)
+        .filter(|(tower, _)| **tower == Tower::Dirt)
+        .count();
+    (merged, dirt)
+}
+
+#[test]
+fn merging_two_identical_gems_refines_once() {
+    let mut game = TestGame::new();
+    let flawed = Tower::Gem {
+        typ: GemType::Emerald,
+        quality: GemQuality::Flawed,
+    };
+
+    // The third emerald isn't needed for the merge and turns to dirt when the merged gem is picked
+    assert_eq!(merge(&mut game, 3), (Some(flawed), 2));
+    assert_eq!(game.phase(), Phase::Spawn);
+}
+
+#[test]
+fn merging_four_identical_gems_refines_twice() {
+    let mut game = TestGame::new();
+    let normal = Tower::Gem {
+        typ: GemType::Emerald,
+        quality: GemQuality::Normal,
+    };
+
+    assert_eq!(merge(&mut game, 4), (Some(normal), 3));
+}
+
+#[test]
+fn bad_luck_protection_favours_missing_gem_types() {
+    let mut chances = GemChances {
+        types: vec![(GemType::Emerald, 2), (GemType::Opal, 2)],
+        ..default()
+    };
+    let mut droughts = GemDroughts::default();
+    droughts.insert(GemType::Opal, 4);
+    assert_eq!(
+        chances.type_weights(&droughts),
+        [(GemType::Emerald, 2), (GemType::Opal, 2)]
+    );
+
+    chances.bad_luck_protection = true;
+    assert_eq!(
+        chances.type_weights(&droughts),
+        [(GemType::Emerald, 2), (GemType::Opal, 6)]
+    );
+
+    let typ = chances.roll_type(&mut droughts, &mut GameRng::new(1));
+    let other = if typ == GemType::Opal {
+        GemType::Emerald
+    } else {
+        GemType::Opal
+    };
+    assert_eq!(droughts[&typ], 0);
+    assert!(droughts[&other] > 0);
+}
+
+#[test]
+fn unusable_gem_tables_fall_back_to_the_defaults() {
+    let chances = GemChances {
+        types: vec![(GemType::Emerald, 0)],
+        qualities: vec![vec![(GemQuality::Perfect, 1)]],
+        bad_luck_protection: false,
+    }
+    .validated();
+
+    assert_eq!(chances.types, GemChances::default().types);
+    // Past the last level the last table is used
+    assert_eq!(
+        chances.roll_quality(20, &mut GameRng::new(1)),
+        GemQuality::Perfect
+    );
+}
+
+#[test]
+fn huge_gem_weights_roll_without_overflowing() {
+    let mut chances = GemChances {
+        types: vec![(GemType::Emerald, u32::MAX), (GemType::Opal, 1 << 31)],
+        qualities: vec![vec![
+            (GemQuality::Chipped, u32::MAX),
+            (GemQuality::Flawed, 1),
+        ]],
+        bad_luck_protection: true,
+    };
+    let mut droughts = GemDroughts::default();
+    droughts.insert(GemType::Opal, 100);
+    assert_eq!(
+        chances.type_weights(&droughts),
+        [(GemType::Emerald, u32::MAX), (GemType::Opal, u32::MAX)]
+    );
+
+    chances.types[1].1 = 1;
+    let mut rng = GameRng::new(1);
+    chances.roll_type(&mut droughts, &mut rng);
+    chances.roll_quality(0, &mut rng);
+}